@@ -0,0 +1,152 @@
+//! HTTP Client 共用方式的性能测试
+//!
+//! 在本地启动一个简单的 HTTPS（HTTP/1.1）服务，使用编译出的 enum-dir 以字典模式扫描，
+//! 分别指定不同的 --client-count（等于并发数时相当于每个 worker 独立一个 Client），
+//! 统计服务端完成的 TLS 握手次数、收到的请求数以及整个扫描的耗时和吞吐量。
+//!
+//! 运行方式：cargo bench --bench client_pool
+
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::process::Command;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+// 并发的 worker 数量，与 enum-dir 的 -n 参数对应
+const WORKERS: usize = 200;
+// 每轮测试的字典条目数，即发出的请求数
+const REQUESTS: usize = 20000;
+
+/**
+ * 服务端的统计，handshakes 为完成的 TLS 握手次数
+ */
+#[derive(Default)]
+struct Counters {
+    handshakes: AtomicUsize,
+    requests: AtomicUsize,
+}
+
+/**
+ * 使用自签名证书构建 TLS 配置，enum-dir 默认不校验证书
+ */
+fn tls_acceptor() -> TlsAcceptor {
+    let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_owned()]).unwrap();
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(cert.serialize_der().unwrap())],
+            PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/**
+ * 启动本地测试服务，所有请求都返回 404，返回监听的地址以及统计
+ */
+async fn start_server() -> (String, Arc<Counters>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let counters = Arc::new(Counters::default());
+    let acceptor = tls_acceptor();
+
+    let server_counters = Arc::clone(&counters);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let acceptor = acceptor.clone();
+            let counters = Arc::clone(&server_counters);
+            tokio::spawn(async move {
+                let mut stream = match acceptor.accept(stream).await {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+                counters.handshakes.fetch_add(1, Ordering::Relaxed);
+                let mut buffer = vec![0u8; 8192];
+                let mut pending = vec![];
+                loop {
+                    let n = match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => n,
+                    };
+                    pending.extend_from_slice(&buffer[..n]);
+                    // 每收到一个完整的请求头回复一次
+                    while let Some(idx) = pending.windows(4).position(|it| it == b"\r\n\r\n") {
+                        pending.drain(..idx + 4);
+                        counters.requests.fetch_add(1, Ordering::Relaxed);
+                        let response = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+                        if stream.write_all(response).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    (format!("https://{}/", addr), counters)
+}
+
+/**
+ * 运行一轮测试，关闭基准页面、敏感内容检测和 canary，只保留字典中的请求
+ */
+async fn run(dir: &Path, client_count: usize) {
+    let (target, counters) = start_server().await;
+
+    let started = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_enum-dir"))
+        .current_dir(dir)
+        .args(["-t", &target, "-d", "dict.txt", "-o", "out.txt"])
+        .args(["-n", &WORKERS.to_string()])
+        .args(["--client-count", &client_count.to_string()])
+        .args(["--no-baseline", "--no-detect", "--canary-interval", "0"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert!(status.success());
+
+    let requests = counters.requests.load(Ordering::Relaxed);
+    println!(
+        "{:<10} {:>12} {:>10} {:>10.2} {:>10.0}",
+        client_count,
+        counters.handshakes.load(Ordering::Relaxed),
+        requests,
+        elapsed.as_secs_f64(),
+        requests as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("enum-dir-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dict = (0..REQUESTS)
+        .map(|i| format!("path{}", i))
+        .collect::<Vec<String>>()
+        .join("\n");
+    std::fs::write(dir.join("dict.txt"), dict).unwrap();
+
+    println!("workers: {}, requests: {}", WORKERS, REQUESTS);
+    println!(
+        "{:<10} {:>12} {:>10} {:>10} {:>10}",
+        "clients", "handshakes", "requests", "time(s)", "req/s"
+    );
+    // client-count 等于并发数时，每个 worker 独立一个 Client
+    for client_count in [WORKERS, 4, 1] {
+        run(&dir, client_count).await;
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,189 @@
+//! builder -> worker -> saver 流水线的性能测试
+//!
+//! 分别模拟两种实现：
+//! - polling：worker 通过 try_recv 轮询任务通道，通道为空时加锁检查 builder 状态；saver 通道为空时休眠 500ms 后重试
+//! - closing：builder 结束后关闭通道，worker 和 saver 通过 recv 等待，通道关闭后直接退出
+//!
+//! 请求通过固定的延迟模拟，builder 按固定速率生成任务，统计总耗时、进程 CPU 时间以及最后一个结果产生后到 saver 退出的延迟。
+//!
+//! 运行方式：cargo bench --bench pipeline
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+// 并发的 worker 数量
+const WORKERS: usize = 200;
+// 任务总数
+const TASKS: usize = 5000;
+// 模拟的单个请求耗时
+const REQUEST_LATENCY: Duration = Duration::from_millis(5);
+// builder 每生成 BATCH 个任务休眠一次，模拟生成任务比请求更慢的情况
+const BATCH: usize = 50;
+const BATCH_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(PartialEq, Eq)]
+enum Status {
+    Running,
+    Stop,
+}
+
+struct Context {
+    builder: Status,
+    workers: Vec<Status>,
+}
+
+/**
+ * 进程已使用的 CPU 时间（用户态 + 内核态），读取 /proc/self/stat，非 Linux 平台返回 0
+ */
+fn cpu_time() -> Duration {
+    let stat = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+    // 进程名中可能有空格，从右括号之后开始解析
+    let fields = stat
+        .rsplit_once(')')
+        .map(|it| it.1.split_whitespace().collect::<Vec<&str>>())
+        .unwrap_or_default();
+    if fields.len() < 13 {
+        return Duration::ZERO;
+    }
+    let utime = fields[11].parse::<u64>().unwrap_or(0);
+    let stime = fields[12].parse::<u64>().unwrap_or(0);
+    // 绝大多数 Linux 系统的时钟频率为 100
+    Duration::from_millis((utime + stime) * 10)
+}
+
+async fn build(tx: async_channel::Sender<usize>) {
+    for i in 0..TASKS {
+        if i % BATCH == 0 {
+            tokio::time::sleep(BATCH_INTERVAL).await;
+        }
+        let _ = tx.send(i).await;
+    }
+}
+
+async fn polling() -> Duration {
+    let ctx = Arc::new(Mutex::new(Context {
+        builder: Status::Running,
+        workers: (0..WORKERS).map(|_| Status::Running).collect(),
+    }));
+    let (task_tx, task_rx) = async_channel::bounded::<usize>(1024);
+    let (result_tx, result_rx) = async_channel::bounded::<Instant>(1024);
+
+    let builder_ctx = Arc::clone(&ctx);
+    let builder = tokio::spawn(async move {
+        build(task_tx).await;
+        builder_ctx.lock().await.builder = Status::Stop;
+    });
+
+    let mut workers = vec![];
+    for idx in 0..WORKERS {
+        let ctx = Arc::clone(&ctx);
+        let task_rx = task_rx.clone();
+        let result_tx = result_tx.clone();
+        workers.push(tokio::spawn(async move {
+            loop {
+                match task_rx.try_recv() {
+                    Ok(_) => {
+                        tokio::time::sleep(REQUEST_LATENCY).await;
+                        let _ = result_tx.send(Instant::now()).await;
+                    }
+                    Err(_) => {
+                        if ctx.lock().await.builder == Status::Stop && task_rx.is_empty() {
+                            break;
+                        }
+                    }
+                }
+            }
+            ctx.lock().await.workers[idx] = Status::Stop;
+        }));
+    }
+
+    let saver_ctx = Arc::clone(&ctx);
+    let saver = tokio::spawn(async move {
+        let mut last = Instant::now();
+        loop {
+            if let Ok(v) = result_rx.try_recv() {
+                last = v;
+            } else if !saver_ctx.lock().await.workers.contains(&Status::Running)
+                && result_rx.is_empty()
+            {
+                break;
+            } else {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        }
+        last.elapsed()
+    });
+
+    let _ = builder.await;
+    for w in workers {
+        let _ = w.await;
+    }
+    saver.await.unwrap()
+}
+
+async fn closing() -> Duration {
+    let (task_tx, task_rx) = async_channel::bounded::<usize>(1024);
+    let (result_tx, result_rx) = async_channel::bounded::<Instant>(1024);
+
+    let builder = tokio::spawn(async move {
+        build(task_tx.clone()).await;
+        task_tx.close();
+    });
+
+    let mut workers = vec![];
+    for _ in 0..WORKERS {
+        let task_rx = task_rx.clone();
+        let result_tx = result_tx.clone();
+        workers.push(tokio::spawn(async move {
+            while task_rx.recv().await.is_ok() {
+                tokio::time::sleep(REQUEST_LATENCY).await;
+                let _ = result_tx.send(Instant::now()).await;
+            }
+        }));
+    }
+    drop(result_tx);
+
+    let saver = tokio::spawn(async move {
+        let mut last = Instant::now();
+        while let Ok(v) = result_rx.recv().await {
+            last = v;
+        }
+        last.elapsed()
+    });
+
+    let _ = builder.await;
+    for w in workers {
+        let _ = w.await;
+    }
+    saver.await.unwrap()
+}
+
+#[tokio::main]
+async fn main() {
+    println!(
+        "workers: {}, tasks: {}, request latency: {:?}",
+        WORKERS, TASKS, REQUEST_LATENCY
+    );
+    println!(
+        "{:<10} {:>10} {:>10} {:>14}",
+        "mode", "wall(ms)", "cpu(ms)", "exit delay(ms)"
+    );
+
+    for name in ["polling", "closing"] {
+        let cpu = cpu_time();
+        let started = Instant::now();
+        let exit_delay = match name {
+            "polling" => polling().await,
+            _ => closing().await,
+        };
+        println!(
+            "{:<10} {:>10} {:>10} {:>14}",
+            name,
+            started.elapsed().as_millis(),
+            (cpu_time() - cpu).as_millis(),
+            exit_delay.as_millis()
+        );
+    }
+}
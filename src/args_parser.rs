@@ -1,6 +1,20 @@
-use clap::{crate_version, value_parser, App, AppSettings, Arg, ArgAction, ArgMatches};
+use crate::engines::builder::{self, TaskOrder};
+use crate::engines::combine::{Combination, CombineMode};
+use crate::engines::distributed::Role;
+use crate::engines::expr::Expr;
+use crate::engines::fuzz::{self, FuzzPoints};
+use crate::engines::keyspace::Shard;
+use crate::engines::redirect::RedirectPolicy;
+use crate::engines::rules::{build_rule_set, RuleSet};
+use crate::engines::vhost;
+use crate::engines::waf::WafPolicy;
+use clap::{
+    crate_version, value_parser, App, AppSettings, Arg, ArgAction, ArgMatches, ValueSource,
+};
 use derivative::Derivative;
-use log::debug;
+use log::{debug, error, info};
+use std::net::SocketAddr;
+use std::time::Duration;
 use tldextract::TldOption;
 use url::Host;
 
@@ -9,6 +23,7 @@ use url::Host;
 pub struct AppArgs {
     pub target: String,
     pub task_count: usize,
+    pub client_count: usize,
     pub request_method: String,
     pub output: String,
     pub suffix: String,
@@ -18,11 +33,42 @@ pub struct AppArgs {
     pub random_user_agent: bool,
     pub cookies: Option<String>,
     pub headers: Vec<String>,
+    pub body: Option<String>,
+    pub fuzz: FuzzPoints,
+    pub root_domain: Option<String>,
+    pub vhost_addrs: Vec<SocketAddr>,
     pub http_retries: usize,
-    pub proxy: Option<String>,
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub pool_max_idle: Option<usize>,
+    pub tcp_keepalive: Option<Duration>,
+    pub tcp_nodelay: bool,
+    pub proxies: Vec<String>,
     pub dict_path: Option<String>,
+    pub combination: Option<Combination>,
     pub black_words: Option<String>,
+    pub rules: RuleSet,
+    pub filter_expr: Option<Expr>,
+    pub dedup_threshold: u64,
+    pub redirect_policy: RedirectPolicy,
+    pub max_redirects: usize,
+    pub listing_depth: usize,
+    pub no_detect: bool,
+    pub no_baseline: bool,
+    pub waf_policy: WafPolicy,
+    pub waf_pause: u64,
+    pub canary_url: Option<String>,
+    pub canary_interval: u64,
     pub fixed_length: bool,
+    pub shard: Shard,
+    pub order: TaskOrder,
+    pub seed: u64,
+    pub role: Role,
+    // 不输出到调试日志中
+    #[derivative(Debug = "ignore")]
+    pub token: Option<String>,
     pub debug_mode: bool,
 
     // not in cli args.
@@ -30,10 +76,39 @@ pub struct AppArgs {
     pub user_agent_list: Vec<String>,
 }
 
+impl AppArgs {
+    /**
+     * 是否使用字典模式，指定了字典或者字典组合模板时为 true
+     */
+    pub fn dict_mode(&self) -> bool {
+        self.dict_path.is_some() || self.combination.is_some()
+    }
+}
+
+/**
+ * 合并多个分片的结果文件
+ */
+#[derive(Debug)]
+pub struct MergeArgs {
+    pub output: String,
+    pub inputs: Vec<String>,
+}
+
+/**
+ * 命令行对应的操作，默认为扫描
+ */
+#[derive(Debug)]
+pub enum Command {
+    Scan(Box<AppArgs>),
+    Merge(MergeArgs),
+}
+
 fn get_arg_matches() -> ArgMatches {
     App::new("enum-dir")
         .setting(AppSettings::ArgRequiredElseHelp)
         .setting(AppSettings::DeriveDisplayOrder)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
         .about("Enum dir/path/file on target URL.")
         .version(crate_version!())
         .arg(
@@ -52,6 +127,30 @@ fn get_arg_matches() -> ArgMatches {
                 .takes_value(true)
                 .default_missing_value(""),
         )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .help("组合多个字典的模板，例如 {DIRS}/{FILES}%EXT%，{NAME} 为通过 NAME=FILE 指定的字典，指定后使用字典模式")
+                .takes_value(true)
+                .conflicts_with("dict")
+                .requires("wordlists"),
+        )
+        .arg(
+            Arg::new("combine")
+                .long("combine")
+                .help("多个字典的组合方式，cluster-bomb 为笛卡尔积，pitchfork 为按行一一对应，默认为 cluster-bomb")
+                .takes_value(true)
+                .default_value("cluster-bomb"),
+        )
+        .arg(
+            Arg::new("wordlists")
+                .help("模板中使用的字典，格式为 NAME=FILE，可指定多个")
+                .value_name("NAME=FILE")
+                .takes_value(true)
+                .multiple_values(true)
+                .requires("template")
+                .value_parser(value_parser!(String)),
+        )
         .arg(
             Arg::new("length")
                 .short('l')
@@ -67,6 +166,47 @@ fn get_arg_matches() -> ArgMatches {
                 .help("固定枚举长度，而非枚举 1..=length ")
                 .takes_value(false)
         )
+        .arg(
+            Arg::new("shard")
+                .long("shard")
+                .help("只扫描第 K 个分片，格式为 K/N，N 个分片分别在不同的机器上运行时恰好覆盖全部任务一次")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("order")
+                .long("order")
+                .help("任务的生成顺序，lexical 为字典序，random 为由种子决定的伪随机顺序，interleave 为在不同目录之间轮流扫描，默认为 lexical")
+                .takes_value(true)
+                .default_value("lexical")
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .help("随机顺序的种子，默认随机生成，相同的种子生成相同的顺序")
+                .takes_value(true)
+                .value_parser(value_parser!(u64))
+        )
+        .arg(
+            Arg::new("coordinator")
+                .long("coordinator")
+                .help("以协调者模式运行，监听指定的地址，例如 0.0.0.0:7878，只生成任务和保存结果，请求由远程 worker 发送")
+                .takes_value(true)
+                .conflicts_with("worker")
+                .requires("token")
+        )
+        .arg(
+            Arg::new("worker")
+                .long("worker")
+                .help("以远程 worker 模式运行，连接到指定地址的协调者，从协调者获取任务并返回结果，其余参数需要与协调者一致")
+                .takes_value(true)
+                .requires("token")
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .help("分布式模式下协调者和远程 worker 握手时使用的认证 token，双方需要一致")
+                .takes_value(true)
+        )
         .arg(
             Arg::new("method")
                 .short('m')
@@ -84,6 +224,14 @@ fn get_arg_matches() -> ArgMatches {
                 .takes_value(true)
                 .value_parser(value_parser!(usize)),
         )
+        .arg(
+            Arg::new("client-count")
+                .long("client-count")
+                .help("所有并发共用的 HTTP Client（连接池）数量，默认为1")
+                .default_value("1")
+                .takes_value(true)
+                .value_parser(value_parser!(usize)),
+        )
         .arg(
             Arg::new("suffix")
                 .short('s')
@@ -122,6 +270,26 @@ fn get_arg_matches() -> ArgMatches {
                 .takes_value(true)
                 .value_parser(value_parser!(String))
         )
+        .arg(
+            Arg::new("vhost")
+                .long("vhost")
+                .help("虚拟主机发现模式，连接的目标固定为 target，字典中的候选主机名作为 Host 头和 TLS SNI，与随机主机名的响应不同时记录")
+                .takes_value(false)
+                .conflicts_with("proxy")
+        )
+        .arg(
+            Arg::new("root-domain")
+                .long("root-domain")
+                .help("vhost 模式下替换字典中 %ROOTDOMAIN% 的根域名，默认从 target 中提取")
+                .takes_value(true)
+                .requires("vhost")
+        )
+        .arg(
+            Arg::new("data")
+                .long("data")
+                .help("请求体，未指定 method 时使用 POST 方法")
+                .takes_value(true)
+        )
         .arg(
             Arg::new("user-agent")
                 .long("user-agent")
@@ -143,12 +311,127 @@ fn get_arg_matches() -> ArgMatches {
                 .default_value("2")
                 .value_parser(value_parser!(usize))
         )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("12")
+                .value_parser(value_parser!(f64))
+                .help("单个请求的总超时秒数，包括连接、发送请求和读取响应体，设置为0时不限制，默认为12")
+        )
+        .arg(
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .takes_value(true)
+                .value_parser(value_parser!(f64))
+                .help("建立连接的超时秒数，默认不单独限制")
+        )
+        .arg(
+            Arg::new("read-timeout")
+                .long("read-timeout")
+                .takes_value(true)
+                .value_parser(value_parser!(f64))
+                .help("读取响应体时，两次收到数据之间的超时秒数，默认不单独限制")
+        )
+        .arg(
+            Arg::new("pool-idle-timeout")
+                .long("pool-idle-timeout")
+                .takes_value(true)
+                .default_value("90")
+                .value_parser(value_parser!(f64))
+                .help("连接池中空闲连接保留的秒数，设置为0时不限制，默认为90")
+        )
+        .arg(
+            Arg::new("pool-max-idle")
+                .long("pool-max-idle")
+                .takes_value(true)
+                .value_parser(value_parser!(usize))
+                .help("连接池中每个 host 最多保留的空闲连接数，默认不限制")
+        )
+        .arg(
+            Arg::new("tcp-keepalive")
+                .long("tcp-keepalive")
+                .takes_value(true)
+                .value_parser(value_parser!(f64))
+                .help("开启 TCP keepalive，并设置探测间隔秒数，默认关闭")
+        )
+        .arg(
+            Arg::new("no-tcp-nodelay")
+                .long("no-tcp-nodelay")
+                .takes_value(false)
+                .help("关闭 TCP_NODELAY，默认开启")
+        )
         .arg(
             Arg::new("proxy")
+                .action(ArgAction::Append)
                 .long("proxy")
                 .short('p')
                 .takes_value(true)
-                .help("socks5 代理或 http 代理，例如 socks5://127.0.0.1:1080")
+                .help("socks5 代理或 http 代理，例如 socks5://127.0.0.1:1080，可指定多次，WAF 策略为 rotate 时依次轮换")
+        )
+        .arg(
+            Arg::new("redirect")
+                .long("redirect")
+                .takes_value(true)
+                .default_value("follow")
+                .help("跳转策略，off 为不跟随，follow 为跟随所有跳转，same-host 为只跟随同 host 的跳转，默认为 follow")
+        )
+        .arg(
+            Arg::new("max-redirects")
+                .long("max-redirects")
+                .takes_value(true)
+                .default_value("10")
+                .value_parser(value_parser!(usize))
+                .help("最多跟随的跳转次数，默认为10")
+        )
+        .arg(
+            Arg::new("listing-depth")
+                .long("listing-depth")
+                .takes_value(true)
+                .default_value("3")
+                .value_parser(value_parser!(usize))
+                .help("发现目录列表时，递归解析其中文件和子目录的最大深度，设置为 0 时只标记不解析，默认为3")
+        )
+        .arg(
+            Arg::new("no-detect")
+                .long("no-detect")
+                .takes_value(false)
+                .help("关闭敏感内容检测，默认会检测命中结果中的密钥、数据库连接串、phpinfo、报错堆栈、SQL 导出等内容")
+        )
+        .arg(
+            Arg::new("no-baseline")
+                .long("no-baseline")
+                .takes_value(false)
+                .help("关闭 soft-404 识别，默认会为每个目录请求一个随机路径作为基准，与基准一致的结果不记录")
+        )
+        .arg(
+            Arg::new("waf-policy")
+                .long("waf-policy")
+                .takes_value(true)
+                .default_value("pause")
+                .help("疑似被 WAF 拦截时的处理策略，ignore 为只警告，pause 为暂停后继续，rotate 为轮换代理和 UA 后继续，abort 为终止扫描，默认为 pause")
+        )
+        .arg(
+            Arg::new("waf-pause")
+                .long("waf-pause")
+                .takes_value(true)
+                .default_value("60")
+                .value_parser(value_parser!(u64))
+                .help("被 WAF 拦截后暂停的秒数，默认为60")
+        )
+        .arg(
+            Arg::new("canary-url")
+                .long("canary-url")
+                .takes_value(true)
+                .help("用于监控目标是否存活的 URL，可以是完整的 URL 或相对于 target 的路径，默认为 target 根目录")
+        )
+        .arg(
+            Arg::new("canary-interval")
+                .long("canary-interval")
+                .takes_value(true)
+                .default_value("30")
+                .value_parser(value_parser!(u64))
+                .help("请求 canary URL 的间隔秒数，请求失败或状态码变化时暂停扫描，恢复后继续，设置为0时关闭，默认为30")
         )
         .arg(
             Arg::new("black-words")
@@ -156,12 +439,75 @@ fn get_arg_matches() -> ArgMatches {
                 .takes_value(true)
                 .help("黑名单关键字，默认为空，设置后当页面内容出现指定的关键字时，认为页面不存在，不记录到结果中。开启该功能后，自动切换为 GET 方法。")
         )
+        .arg(
+            Arg::new("match-regex")
+                .action(ArgAction::Append)
+                .long("match-regex")
+                .takes_value(true)
+                .value_parser(value_parser!(String))
+                .help("保留匹配的结果，格式为 [target:]regex，target 可以是 body、status、headers 或 header:<Name>，默认为 body，可指定多次")
+        )
+        .arg(
+            Arg::new("filter-regex")
+                .action(ArgAction::Append)
+                .long("filter-regex")
+                .takes_value(true)
+                .value_parser(value_parser!(String))
+                .help("丢弃匹配的结果，格式同 --match-regex，例如 header:Set-Cookie:waf_block，可指定多次")
+        )
+        .arg(
+            Arg::new("rule-logic")
+                .long("rule-logic")
+                .takes_value(true)
+                .default_value("or")
+                .help("多条 match / filter 规则的组合方式，可选 and 或 or，默认为 or")
+        )
+        .arg(
+            Arg::new("rules-file")
+                .long("rules-file")
+                .takes_value(true)
+                .help("从文件中加载规则，每行一条，格式为 match <rule>、filter <rule> 或 logic and|or")
+        )
+        .arg(
+            Arg::new("expr")
+                .long("expr")
+                .takes_value(true)
+                .help("结果过滤表达式，只记录表达式为真的结果，例如 'status in [200,403] && size > 120 && !body ~ \"not found\"'，可用字段见 README")
+        )
+        .arg(
+            Arg::new("dedup-threshold")
+                .long("dedup-threshold")
+                .takes_value(true)
+                .default_value("10")
+                .value_parser(value_parser!(u64))
+                .help("相同或相似的响应超过该数量后，整个聚类折叠为一条汇总记录，设置为 0 时不折叠，默认为10")
+        )
         .arg(
             Arg::new("debug")
             .long("debug")
             .takes_value(false)
             .help("调试模式")
         )
+        .subcommand(
+            App::new("merge")
+                .about("合并多个分片的结果文件，去除重复的结果后按 URL 排序")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("合并后的输出文件路径")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("inputs")
+                        .help("各个分片的结果文件")
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
         .get_matches()
 }
 
@@ -178,20 +524,26 @@ async fn extract_target(raw_target: &String) -> Result<String, &'static str> {
         auto_detect = true;
         format!("http://{}", raw_target)
     };
-    let uri = reqwest::Url::parse(&tmp_target).unwrap();
+    // target 中的 FUZZ 标记可能位于 host 或端口中，替换为合法的值后再校验
+    let fuzz_target = raw_target.contains(fuzz::MARKER);
+    let checked_target = tmp_target.replace(fuzz::MARKER, "1");
+    let uri = reqwest::Url::parse(&checked_target).map_err(|_| "target有误!")?;
     match uri.host().unwrap() {
         Host::Ipv4(_) => {}
         _ => {
             let tld_extractor = TldOption::default().cache_path(".tld_cache").build();
-            let tld_result = tld_extractor.extract(&tmp_target).unwrap();
+            let tld_result = tld_extractor.extract(&checked_target).unwrap();
             if tld_result.suffix.is_none() {
                 return Err("target有误!");
             }
         }
     };
 
-    // 自动探测协议
-    let target = if auto_detect {
+    // 自动探测协议，target 中包含 FUZZ 标记时无法探测，使用 http 协议
+    let target = if auto_detect && fuzz_target {
+        info!("target 中包含 {} 标记，使用 http 协议", fuzz::MARKER);
+        tmp_target
+    } else if auto_detect {
         // 需要探测真正的协议，如果 http 没有跳转，就都使用 http 协议，如果 http 协议跳转到 https 了，就使用 https 协议
         debug!("目标中未提供协议，自动探测...");
         let client = reqwest::Client::builder().build().unwrap();
@@ -204,15 +556,38 @@ async fn extract_target(raw_target: &String) -> Result<String, &'static str> {
         raw_target.clone()
     };
 
-    if target.ends_with('/') {
+    // 包含 FUZZ 标记时标记的位置由用户决定，不再补充结尾的 /
+    if target.ends_with('/') || fuzz_target {
         Ok(target)
     } else {
         Ok(format!("{}/", target))
     }
 }
 
-pub async fn parse() -> Result<AppArgs, &'static str> {
+/**
+ * 从 target 中提取根域名，例如 https://www.example.co.uk/ -> example.co.uk，target 为 IP 时返回 None
+ */
+fn get_root_domain(target: &str) -> Option<String> {
+    let host = reqwest::Url::parse(target).ok()?.host()?.to_owned();
+    if !matches!(host, Host::Domain(_)) {
+        return None;
+    }
+    let tld_extractor = TldOption::default().cache_path(".tld_cache").build();
+    let tld_result = tld_extractor.extract(target).ok()?;
+    Some(format!("{}.{}", tld_result.domain?, tld_result.suffix?).to_lowercase())
+}
+
+pub async fn parse() -> Result<Command, &'static str> {
     let options = get_arg_matches();
+
+    // 合并分片结果
+    if let Some(("merge", sub_options)) = options.subcommand() {
+        return Ok(Command::Merge(MergeArgs {
+            output: sub_options.get_one::<String>("output").unwrap().to_owned(),
+            inputs: get_many_strings(sub_options, "inputs"),
+        }));
+    }
+
     let mut app_args = AppArgs::default();
 
     // 解析 target 参数
@@ -227,11 +602,63 @@ pub async fn parse() -> Result<AppArgs, &'static str> {
         app_args.dict_path = None;
     }
 
+    // 通过模板组合多个字典
+    if let Some(template) = options.get_one::<String>("template") {
+        let mode = CombineMode::parse(options.get_one::<String>("combine").unwrap())?;
+        let wordlists = get_many_strings(&options, "wordlists");
+        app_args.combination = Some(Combination::parse(template, &wordlists, mode)?);
+    }
+
     app_args.length = options.get_one::<usize>("length").unwrap().to_owned();
     app_args.fixed_length = options.is_present("fixed-length");
+    if let Some(shard) = options.get_one::<String>("shard") {
+        app_args.shard = Shard::parse(shard)?;
+    }
+
+    // 任务顺序，随机顺序未指定种子时随机生成一个，便于复现
+    app_args.order = TaskOrder::parse(options.get_one::<String>("order").unwrap())?;
+    app_args.seed = match options.get_one::<u64>("seed") {
+        Some(v) => v.to_owned(),
+        None => {
+            // 枚举模式的分片作用在排列后的编号上，各个分片需要使用相同的种子
+            if app_args.order == TaskOrder::Random
+                && app_args.shard.is_sharded()
+                && !app_args.dict_mode()
+            {
+                return Err("随机顺序的枚举模式分片扫描时，需要通过 --seed 指定相同的种子！");
+            }
+            rand::random()
+        }
+    };
+    // 枚举模式下所有任务都在同一目录中，无法在目录之间轮流扫描
+    if app_args.order == TaskOrder::Interleave && !app_args.dict_mode() {
+        return Err("interleave 顺序只能在字典模式下使用！");
+    }
+    if app_args.order == TaskOrder::Random {
+        info!("随机顺序的种子为 {}，可以通过 --seed 复现", app_args.seed);
+    }
+
+    if let Some(listen) = options.get_one::<String>("coordinator") {
+        app_args.role = Role::Coordinator(listen.to_owned());
+    } else if let Some(coordinator) = options.get_one::<String>("worker") {
+        app_args.role = Role::Worker(coordinator.to_owned());
+    }
+    app_args.token = options.get_one::<String>("token").cloned();
     app_args.task_count = options.get_one::<usize>("task-count").unwrap().to_owned();
+    app_args.client_count = options.get_one::<usize>("client-count").unwrap().to_owned();
+    if app_args.client_count == 0 {
+        return Err("client-count 不能为0！");
+    }
     app_args.suffix = options.get_one::<String>("suffix").unwrap().to_owned();
     app_args.empty_suffix = options.is_present("empty-suffix");
+
+    // 枚举模式下任务按 u64 编号，keyspace 超出 u64 的范围时无法切分区间和分片
+    if !app_args.dict_mode() {
+        let keyspace = builder::enum_keyspace(&app_args);
+        if keyspace.total(builder::enum_lengths(&app_args)).is_none() {
+            return Err("枚举的 keyspace 超出了 u64 的范围，请减小 -l 或减少后缀的数量！");
+        }
+    }
     app_args.output = if let Some(o) = options.get_one::<String>("output") {
         o.to_owned()
     } else {
@@ -244,7 +671,17 @@ pub async fn parse() -> Result<AppArgs, &'static str> {
             .replace('/', "_")
             .trim_matches('_')
             .to_owned();
-        format!("{}.txt", filename)
+        // 分片时在文件名中标记分片，便于合并
+        if app_args.shard.is_sharded() {
+            format!(
+                "{}.shard-{}-of-{}.txt",
+                filename,
+                app_args.shard.index + 1,
+                app_args.shard.count
+            )
+        } else {
+            format!("{}.txt", filename)
+        }
     };
 
     // 检查 method 是否合法
@@ -262,19 +699,103 @@ pub async fn parse() -> Result<AppArgs, &'static str> {
         return Err("method 错误！");
     }
 
-    // 设置 black words
+    // 指定了请求体但没有指定 method 时使用 POST 方法
+    let default_method = options.value_source("method") == Some(ValueSource::DefaultValue);
+    app_args.body = options.get_one::<String>("data").cloned();
+    if app_args.body.is_some() && default_method {
+        app_args.request_method = "POST".to_owned();
+    }
+
+    // vhost 模式，连接的目标固定为 target 的地址
+    let vhost = options.is_present("vhost");
+    if vhost {
+        if app_args.target.contains(fuzz::MARKER) {
+            return Err("vhost 模式下 target 中不能包含 FUZZ 标记！");
+        }
+        if !app_args.dict_mode() {
+            return Err("vhost 模式需要通过 -d 或 --template 指定候选的主机名！");
+        }
+        app_args.root_domain = match options.get_one::<String>("root-domain") {
+            Some(v) => Some(v.trim_matches('.').to_lowercase()),
+            None => get_root_domain(&app_args.target),
+        };
+        if app_args.root_domain.is_none() {
+            return Err("vhost 模式下无法从 target 中提取根域名，需要通过 --root-domain 指定！");
+        }
+        app_args.vhost_addrs = vhost::resolve_target(&app_args.target).await?;
+        info!(
+            "vhost 模式，连接到 {:?}，根域名为 {}",
+            app_args.vhost_addrs,
+            app_args.root_domain.as_ref().unwrap()
+        );
+        // 需要比较响应体，没有指定 method 时使用 GET 方法
+        if app_args.body.is_none() && default_method {
+            app_args.request_method = "GET".to_owned();
+        }
+    }
+
+    // 设置 black words，需要用到响应体，HEAD 方法自动切换为 GET，指定了请求体时保持 POST
     let black_words = options.get_one::<String>("black-words");
     if black_words.is_some() {
         app_args.black_words = black_words.cloned();
+        if app_args.body.is_none() {
+            app_args.request_method = "GET".to_owned();
+        }
+    }
+
+    // 设置 match / filter 规则，如果规则需要用到响应体，自动切换为 GET 方法
+    let match_rules = get_many_strings(&options, "match-regex");
+    let filter_rules = get_many_strings(&options, "filter-regex");
+    app_args.rules = build_rule_set(
+        &match_rules,
+        &filter_rules,
+        options.get_one::<String>("rule-logic").unwrap(),
+        options.get_one::<String>("rules-file"),
+    )?;
+    if app_args.rules.need_body() && app_args.request_method == "HEAD" {
         app_args.request_method = "GET".to_owned();
     }
 
+    // 解析结果过滤表达式，启动时校验语法
+    if let Some(raw) = options.get_one::<String>("expr") {
+        let expr = Expr::parse(raw).map_err(|e| {
+            error!("{}", e);
+            "表达式错误！"
+        })?;
+        if expr.need_body() && app_args.request_method == "HEAD" {
+            app_args.request_method = "GET".to_owned();
+        }
+        app_args.filter_expr = Some(expr);
+    }
+
+    app_args.dedup_threshold = options
+        .get_one::<u64>("dedup-threshold")
+        .unwrap()
+        .to_owned();
+
     // 获取 UA
     app_args.user_agent = options.get_one::<String>("user-agent").unwrap().to_owned();
 
-    // 获取随机 UA 的设置，默认为 false
+    // WAF 处理策略
+    app_args.waf_policy = WafPolicy::parse(options.get_one::<String>("waf-policy").unwrap())?;
+    app_args.waf_pause = options.get_one::<u64>("waf-pause").unwrap().to_owned();
+
+    // 目标存活监控
+    app_args.canary_url = options.get_one::<String>("canary-url").map(|it| {
+        if it.starts_with("http://") || it.starts_with("https://") {
+            it.to_owned()
+        } else {
+            format!("{}{}", app_args.target, it.trim_start_matches('/'))
+        }
+    });
+    app_args.canary_interval = options
+        .get_one::<u64>("canary-interval")
+        .unwrap()
+        .to_owned();
+
+    // 获取随机 UA 的设置，默认为 false，WAF 策略为 rotate 时也需要用到 UA 列表
     app_args.random_user_agent = options.is_present("random-user-agent");
-    if app_args.random_user_agent {
+    if app_args.random_user_agent || app_args.waf_policy == WafPolicy::Rotate {
         app_args.user_agent_list = read_user_agent();
     }
 
@@ -291,18 +812,75 @@ pub async fn parse() -> Result<AppArgs, &'static str> {
         }
     }
 
+    // 请求中出现 FUZZ 标记时，任务替换标记，而不是作为路径追加到 target 后面
+    app_args.fuzz = FuzzPoints::find(
+        &app_args.target,
+        vhost,
+        &app_args.headers,
+        app_args.cookies.as_ref(),
+        app_args.body.as_ref(),
+    );
+    if app_args.fuzz.enabled() && !vhost {
+        info!("请求中包含 {} 标记，任务将替换标记的内容", fuzz::MARKER);
+    }
+
     // http 重试次数
     let http_retries = options.get_one::<usize>("http-retry").unwrap();
     app_args.http_retries = http_retries.to_owned();
 
+    // 超时和连接池设置
+    app_args.timeout = get_seconds(&options, "timeout")?;
+    app_args.connect_timeout = get_seconds(&options, "connect-timeout")?;
+    app_args.read_timeout = get_seconds(&options, "read-timeout")?;
+    app_args.pool_idle_timeout = get_seconds(&options, "pool-idle-timeout")?;
+    app_args.pool_max_idle = options.get_one::<usize>("pool-max-idle").cloned();
+    app_args.tcp_keepalive = get_seconds(&options, "tcp-keepalive")?;
+    app_args.tcp_nodelay = !options.is_present("no-tcp-nodelay");
+
+    // 跳转策略
+    app_args.redirect_policy =
+        RedirectPolicy::parse(options.get_one::<String>("redirect").unwrap())?;
+    app_args.max_redirects = options
+        .get_one::<usize>("max-redirects")
+        .unwrap()
+        .to_owned();
+
+    app_args.listing_depth = options
+        .get_one::<usize>("listing-depth")
+        .unwrap()
+        .to_owned();
+
+    app_args.no_detect = options.is_present("no-detect");
+    app_args.no_baseline = options.is_present("no-baseline");
+
     // 代理设置
-    let proxy = options.get_one::<String>("proxy");
-    app_args.proxy = proxy.cloned();
+    app_args.proxies = get_many_strings(&options, "proxy");
 
     app_args.debug_mode = options.is_present("debug");
 
     debug!("app_args: {:?}", app_args);
-    Ok(app_args)
+    Ok(Command::Scan(Box::new(app_args)))
+}
+
+fn get_many_strings(options: &ArgMatches, name: &str) -> Vec<String> {
+    match options.get_many::<String>(name) {
+        Some(values) => values.cloned().collect(),
+        None => vec![],
+    }
+}
+
+/**
+ * 读取以秒为单位的参数，未指定或为 0 时返回 None
+ */
+fn get_seconds(options: &ArgMatches, name: &str) -> Result<Option<Duration>, &'static str> {
+    match options.get_one::<f64>(name) {
+        Some(v) if !v.is_finite() || *v < 0.0 => {
+            error!("{} 必须为非负数，当前为 {}", name, v);
+            Err("超时参数有误！")
+        }
+        Some(v) if *v > 0.0 => Ok(Some(Duration::from_secs_f64(*v))),
+        _ => Ok(None),
+    }
 }
 
 fn read_user_agent() -> Vec<String> {
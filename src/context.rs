@@ -1,14 +1,42 @@
+use crate::engines::baseline::BaselineCell;
+use crate::engines::detector::Finding;
+use crate::engines::stats::Stats;
+use crate::engines::waf::WafMonitor;
 use indicatif::{ProgressBar, ProgressStyle};
+use log::info;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+/**
+ * 扫描进度，各个协程直接通过原子变量更新，不需要加锁
+ */
+#[derive(Debug, Default)]
+pub struct Progress {
+    // 任务总数，包括扫描过程中额外产生的任务
+    pub total: AtomicU64,
+    // 已经处理完成的任务数
+    pub done: AtomicU64,
+    // 记录到结果中的数量
+    pub found: AtomicU64,
+    // 正在运行的 worker 数量
+    pub running_workers: AtomicUsize,
+}
+
+/**
+ * 进度条，只负责展示 Progress 中的数据
+ */
+#[derive(Debug, Clone)]
 pub struct EnumProgressBar {
-    pub total: u64,
     pub instance: ProgressBar,
 }
 
 impl EnumProgressBar {
-    pub fn new(total: u64) -> Self {
-        let pb = ProgressBar::new(total);
+    pub fn new() -> Self {
+        let pb = ProgressBar::new(0);
         pb.set_style(
             ProgressStyle::with_template(
                 "{prefix:>12.cyan.bold} [{bar:57}] {pos}/{len} {wide_msg}",
@@ -17,42 +45,158 @@ impl EnumProgressBar {
             .progress_chars("=> "),
         );
         pb.set_prefix("Scanning");
-        Self {
-            total,
-            instance: pb,
-        }
+        Self { instance: pb }
+    }
+
+    /**
+     * 根据当前的进度刷新进度条
+     */
+    pub fn render(&self, progress: &Progress) {
+        self.instance
+            .set_length(progress.total.load(Ordering::Relaxed));
+        self.instance
+            .set_position(progress.done.load(Ordering::Relaxed));
+        self.instance.set_message(format!(
+            "found: {}, workers: {}",
+            progress.found.load(Ordering::Relaxed),
+            progress.running_workers.load(Ordering::Relaxed)
+        ));
     }
 }
 
+/**
+ * 重试全部失败的任务，worker、canary 和任务队列共用同一个锁
+ */
+#[derive(Debug, Default)]
+pub struct Failures {
+    // 上一次 canary 检查之后重试全部失败的任务
+    pub recent: Vec<String>,
+    // 重试全部失败的任务，以及最后一次的错误
+    pub failed: HashMap<String, String>,
+    // 已经开始对失败的任务进行最后一轮尝试
+    pub retry_pass: bool,
+}
+
+/**
+ * 各个协程共享的状态，每个请求都会检查的标记使用原子变量，其余的状态按用途分别加锁，不再共用一个全局的锁
+ */
 #[derive(Debug)]
 pub struct AppContext {
-    pub builder_status: WorkerStatus,
-    pub worker_status: Vec<WorkerStatus>,
-    pub saver_status: WorkerStatus,
-    pub pb: Option<EnumProgressBar>,
+    start: Instant,
+    // 预检时发现目标不支持 HEAD，所有请求直接使用 GET
+    pub head_fallback: AtomicBool,
+    // 运行时因 405/501 回退为 GET 的请求数量
+    pub head_fallback_count: AtomicU64,
+    // 已经加入队列的条目及其深度
+    pub harvested: Mutex<HashMap<String, usize>>,
+    // 每个目录（以及后缀）的 soft-404 基准页面，获取失败时为 None，同一个 key 只由第一个 worker 请求一次
+    pub baselines: RwLock<HashMap<(String, String), BaselineCell>>,
+    // WAF 拦截检测
+    pub waf: Mutex<WafMonitor>,
+    // 暂停扫描直到该时间，为相对 start 的毫秒数，0 表示没有暂停
+    paused_until: AtomicU64,
+    // 所有 worker 共用的 HTTP Client
+    pub clients: RwLock<Vec<Client>>,
+    // 代理和 UA 的轮换次数，轮换时重新构建 HTTP Client，worker 发现变化后切换
+    pub rotation: AtomicUsize,
+    // 扫描已被终止
+    pub aborted: AtomicBool,
+    // canary 发现目标不可用，暂停扫描直到恢复，需要在持有 failures 锁时修改
+    pub canary_down: AtomicBool,
+    // 重试全部失败的任务
+    pub failures: Mutex<Failures>,
+    // 请求统计，worker 在本地统计，退出时合并进来
+    pub stats: Mutex<Stats>,
 }
 
 impl AppContext {
     pub fn new() -> Self {
         Self {
-            builder_status: WorkerStatus::Init,
-            worker_status: vec![],
-            saver_status: WorkerStatus::Init,
-            pb: None,
+            start: Instant::now(),
+            head_fallback: AtomicBool::new(false),
+            head_fallback_count: AtomicU64::new(0),
+            harvested: Mutex::new(HashMap::new()),
+            baselines: RwLock::new(HashMap::new()),
+            waf: Mutex::new(WafMonitor::default()),
+            paused_until: AtomicU64::new(0),
+            clients: RwLock::new(vec![]),
+            rotation: AtomicUsize::new(0),
+            aborted: AtomicBool::new(false),
+            canary_down: AtomicBool::new(false),
+            failures: Mutex::new(Failures::default()),
+            stats: Mutex::new(Stats::default()),
         }
     }
-}
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum WorkerStatus {
-    Init,
-    Running,
-    Stop,
+    fn elapsed_millis(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    /**
+     * 暂停扫描一段时间
+     */
+    pub fn pause(&self, duration: Duration) {
+        let until = self.elapsed_millis() + duration.as_millis() as u64;
+        self.paused_until.store(until.max(1), Ordering::SeqCst);
+    }
+
+    /**
+     * 是否处于暂停中，暂停结束后第一个发现的协程负责清除暂停状态
+     */
+    pub fn paused(&self) -> bool {
+        let until = self.paused_until.load(Ordering::SeqCst);
+        if until == 0 {
+            return false;
+        }
+        if self.elapsed_millis() < until {
+            return true;
+        }
+        if self
+            .paused_until
+            .compare_exchange(until, 0, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            info!("暂停结束，继续扫描");
+        }
+        false
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct EnumResult {
     pub status_code: u16,
+    // 完整的状态行，例如 HTTP/1.1 200 OK
+    pub status_line: String,
+    pub headers: HeaderMap,
     pub url: String,
+    // fuzz 模式下替换 FUZZ 标记的值
+    pub fuzz: Option<String>,
+    // 跟随过的跳转链
+    pub redirects: Vec<RedirectHop>,
+    // 最终的跳转地址
+    pub location: Option<String>,
     pub content: Option<String>,
+    // 响应体的精确 hash 和 simhash 指纹，没有响应体时为 None
+    pub body_hash: Option<u64>,
+    pub simhash: Option<u64>,
+    // 该结果是否由 HEAD 回退为 GET 后得到
+    pub head_fallback: bool,
+    // 该页面是否为目录列表
+    pub dir_listing: bool,
+    // 在响应体中检测到的敏感内容
+    pub findings: Vec<Finding>,
+    // 页面标题
+    pub title: Option<String>,
+    // 响应长度，没有读取响应体时取 Content-Length
+    pub content_length: u64,
+    // 与所在目录的基准页面一致，认为是 soft-404
+    pub soft_404: bool,
+    // 命中的 WAF 拦截页面名称
+    pub waf: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RedirectHop {
+    pub status_code: u16,
+    pub location: String,
 }
@@ -1,113 +1,286 @@
 use regex::Regex;
+use std::collections::HashSet;
 use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 use std::{sync::Arc, time::Duration};
 
 use async_channel::{Receiver, Sender};
+use itertools::Itertools;
 use log::{debug, error, info, warn};
 use rand::prelude::SliceRandom;
-use reqwest::{ClientBuilder, Method};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE, SERVER};
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, Method, RequestBuilder, Response};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::Mutex;
 
-use crate::context::EnumResult;
-use crate::{args_parser::AppArgs, context::AppContext, WorkerStatus};
+use crate::context::{EnumProgressBar, EnumResult, Progress};
+use crate::engines::baseline::get_baseline;
+use crate::engines::dedup::{Deduplicator, Verdict};
+use crate::engines::detector::Detector;
+use crate::engines::keyspace::KeyRange;
+use crate::engines::queue::{Task, TaskQueue};
+use crate::engines::redirect::{get_location, send_with_redirects};
+use crate::engines::seen::seen_key;
+use crate::engines::stats::Stats;
+use crate::engines::vhost::FixedResolver;
+use crate::engines::waf::WafPolicy;
+use crate::{args_parser::AppArgs, context::AppContext};
 
+pub mod baseline;
 pub mod builder;
+pub mod canary;
+pub mod combine;
+pub mod dedup;
+pub mod detector;
+pub mod distributed;
+pub mod expr;
+pub mod fuzz;
+pub mod keyspace;
+pub mod listing;
+pub mod merge;
+pub mod probe;
+pub mod queue;
+pub mod redirect;
+pub mod rules;
+pub mod seen;
+pub mod stats;
+pub mod vhost;
+pub mod waf;
 pub use builder::builder;
+pub use canary::canary;
+pub use merge::merge;
+pub use probe::preflight;
+
+// 对命中结果单独获取响应体，或者运行时回退为 GET 时，最多读取的字节数
+const MAX_HIT_BODY_SIZE: usize = 1024 * 1024;
+// 页面标题最多保留的字符数
+const MAX_TITLE_LENGTH: usize = 80;
 
 pub async fn worker(
     idx: usize,
     args: Arc<AppArgs>,
-    task_channel: Receiver<String>,
+    task_queue: Arc<TaskQueue>,
     result_channel: Sender<Arc<EnumResult>>,
-    app_context: Arc<Mutex<AppContext>>,
+    app_context: Arc<AppContext>,
+    progress: Arc<Progress>,
 ) {
     debug!("engine worker {} start", idx);
-    let target = &args.target;
+    progress.running_workers.fetch_add(1, Ordering::Relaxed);
+    // 所有 worker 共用 client-count 个 HTTP Client 及其连接池
+    let mut rotation = app_context.rotation.load(Ordering::SeqCst);
+    let mut http_client = get_http_client(&app_context, idx);
+    let detector = Detector::new();
+    // 请求统计在本地记录，退出时再合并，避免每个请求都加锁
+    let mut stats = Stats::default();
 
-    // 如果没使用 random user agent，直接在这里把UA写进去
-    let mut builder = ClientBuilder::new()
-        .timeout(Duration::from_secs(12))
-        .danger_accept_invalid_certs(true);
-    if !args.random_user_agent {
-        builder = builder.user_agent(&args.user_agent);
-    }
-
-    // 如果在CLI参数中指定了代理，则把代理设置进去，默认对 http/https 协议都生效
-    if let Some(proxy) = &args.proxy {
-        let _proxy = reqwest::Proxy::all(proxy);
-        if _proxy.is_err() {
-            error!("代理设置错误！");
-            exit(-1);
-        }
-        builder = builder.proxy(_proxy.unwrap());
-    }
+    // 解析出指定的 HTTP Method，如果预检时发现目标不支持 HEAD，则整体切换为 GET
+    let preflight_fallback = app_context.head_fallback.load(Ordering::Relaxed);
+    let method = if preflight_fallback {
+        Method::GET
+    } else {
+        Method::from_bytes(args.request_method.as_bytes()).unwrap()
+    };
 
-    let http_client = builder.build().unwrap();
+    // 正在处理的 keyspace 区间，以及复用的任务和 URL 缓冲区
+    let mut current: Option<KeyRange> = None;
+    let mut task_id = 0;
+    let mut task = String::new();
+    let mut url = String::new();
 
     loop {
-        let task = task_channel.try_recv();
-        let url = match task {
-            Ok(v) => format!("{}{}", target, v),
-            Err(_) => {
-                if app_context.lock().await.builder_status == WorkerStatus::Stop {
-                    break;
-                } else {
-                    continue;
-                }
-            }
-        };
-
-        // 解析出指定的 HTTP Method
-        let method = Method::from_bytes(args.request_method.as_bytes()).unwrap();
-
-        // 如果使用了 random-user-agent 选项，就随机一个 agent 出来，然后塞到头里
-        let mut request = http_client.request(method, &url);
-        if args.random_user_agent {
-            let random_ua = args.user_agent_list.choose(&mut rand::thread_rng());
-            request = request.header("User-Agent", random_ua.unwrap());
+        if app_context.aborted.load(Ordering::SeqCst) {
+            break;
         }
 
-        // 如果在 CLI 参数中设置了 header 则依次添加
-        for header in &args.headers {
-            let header_part = header.splitn(2, ':').collect::<Vec<&str>>();
-
-            // 跳过不合法的header
-            if header_part.len() < 2 {
-                continue;
-            }
-
-            let key = header_part[0].trim();
-            let value = header_part[1].trim();
+        // 被 WAF 拦截后暂停扫描，目标不可用时暂停扫描，等待 canary 确认恢复
+        if app_context.paused() || app_context.canary_down.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            continue;
+        }
 
-            request = request.header(key, value);
+        // 代理和 UA 发生了轮换，切换到重新构建的 HTTP Client
+        let current_rotation = app_context.rotation.load(Ordering::SeqCst);
+        if current_rotation != rotation {
+            rotation = current_rotation;
+            http_client = get_http_client(&app_context, idx);
         }
 
-        // 如果在 CLI 参数中设置了 cookie 则添加一个 cookie 头
-        if let Some(cookie) = &args.cookies {
-            request = request.header("Cookie", cookie);
+        // 当前的 keyspace 区间还有剩余时直接在本地渲染，否则从队列中取下一个任务，队列关闭后不会再有新任务
+        let rendered = current.as_mut().is_some_and(|it| it.next_into(&mut task));
+        if !rendered {
+            match task_queue.recv().await {
+                Some((id, Task::Path(v))) => {
+                    task_id = id;
+                    task = v;
+                }
+                Some((id, Task::Range(range))) => {
+                    task_id = id;
+                    current = Some(range);
+                    continue;
+                }
+                None => break,
+            }
         }
+        fuzz::write_url(&args, &task, &mut url);
 
         // 根据重试策略，进行重试
         if args.debug_mode {
             debug!("try url: {}", url);
         }
+        let mut succeeded = false;
+        let mut last_error = None;
         for c in 0..args.http_retries {
-            match request.try_clone().unwrap().send().await {
-                Ok(r) => {
-                    let code = r.status().as_u16();
-                    let content = if args.request_method != "HEAD" {
-                        Some(r.text().await.unwrap())
+            let started = Instant::now();
+            match send_with_redirects(&http_client, &args, method.clone(), &url, &task).await {
+                Ok((mut r, mut redirects)) => {
+                    let latency = started.elapsed();
+                    let mut code = r.status().as_u16();
+                    let mut status_line = get_status_line(&r);
+                    let mut headers = r.headers().clone();
+                    let mut location = get_location(&r);
+                    let mut final_url = r.url().to_string();
+                    let mut head_fallback = false;
+
+                    // 服务端拒绝了 HEAD 请求，使用 GET 重新请求一次，后续使用 GET 的响应
+                    if method == Method::HEAD && (code == 405 || code == 501) {
+                        if let Ok((get_response, get_redirects)) =
+                            send_with_redirects(&http_client, &args, Method::GET, &url, &task).await
+                        {
+                            code = get_response.status().as_u16();
+                            status_line = get_status_line(&get_response);
+                            headers = get_response.headers().clone();
+                            location = get_location(&get_response);
+                            final_url = get_response.url().to_string();
+                            redirects = get_redirects;
+                            r = get_response;
+                            head_fallback = true;
+                            app_context
+                                .head_fallback_count
+                                .fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+
+                    // 最终的跳转地址，没有跟随的跳转优先，否则使用跳转链中的最后一跳
+                    let location = location
+                        .map(|it| it.to_string())
+                        .or_else(|| redirects.last().map(|it| it.location.clone()));
+
+                    // 根据实际使用的方法决定是否读取响应体，预检或运行时回退为 GET 的响应最多读取 MAX_HIT_BODY_SIZE 字节
+                    let content = if head_fallback || method != Method::HEAD {
+                        let limit = if head_fallback || preflight_fallback {
+                            MAX_HIT_BODY_SIZE
+                        } else {
+                            usize::MAX
+                        };
+                        let buffer = read_body(&mut r, &args, limit).await;
+                        Some(String::from_utf8_lossy(&buffer).into_owned())
                     } else {
                         None
                     };
+                    // 没有读取响应体时，对命中结果单独请求一次响应体，用于目录列表和敏感内容检测
+                    let fetched = match &content {
+                        Some(_) => None,
+                        None => fetch_hit_body(&http_client, &args, code, &final_url, &task).await,
+                    };
+                    let body = content.as_deref().or(fetched.as_deref());
+
+                    // 检测目录列表，并把列出的条目加入任务队列
+                    let dir_listing = body.is_some_and(listing::is_dir_listing);
+                    if dir_listing {
+                        harvest_listing(
+                            &args,
+                            &app_context,
+                            &task_queue,
+                            &task,
+                            body.unwrap(),
+                            &final_url,
+                        );
+                    }
+
+                    // 检测敏感内容
+                    let findings = match body {
+                        Some(v) if code != 404 && !args.no_detect => detector.detect(v),
+                        _ => vec![],
+                    };
+
+                    // 页面标题以及响应长度，没有读取响应体时优先使用 Content-Length
+                    let title = body.and_then(extract_title);
+                    let content_length = match &content {
+                        Some(v) => v.len() as u64,
+                        None => headers
+                            .get(CONTENT_LENGTH)
+                            .and_then(|it| it.to_str().ok())
+                            .and_then(|it| it.parse::<u64>().ok())
+                            .or_else(|| fetched.as_ref().map(|it| it.len() as u64))
+                            .unwrap_or(0),
+                    };
+
+                    let (body_hash, simhash) = match body {
+                        Some(v) => {
+                            let (body_hash, simhash) = dedup::fingerprint(v, &url);
+                            (Some(body_hash), Some(simhash))
+                        }
+                        None => (None, None),
+                    };
+
+                    // 与任务所在目录的基准页面比较，识别 soft-404
+                    let soft_404 = if code != 404 && !args.no_baseline {
+                        get_baseline(&http_client, &args, &app_context, &task)
+                            .await
+                            .is_some_and(|it| {
+                                it.matches(code, simhash, location.as_ref(), content_length)
+                            })
+                    } else {
+                        false
+                    };
+                    // 检测 WAF 拦截，触发后根据策略处理
+                    let waf = waf::detect_block(code, &headers, body);
                     let result = EnumResult {
                         status_code: code,
+                        status_line,
+                        headers,
                         url: url.clone(),
+                        fuzz: args.fuzz.enabled().then(|| task.clone()),
+                        redirects,
+                        location,
                         content,
+                        body_hash,
+                        simhash,
+                        head_fallback,
+                        dir_listing,
+                        findings,
+                        title,
+                        content_length,
+                        soft_404,
+                        waf,
                     };
-                    let _ = result_channel.send(Arc::new(result)).await;
+                    stats.record_response(&task, code, latency);
+                    let mut observation = {
+                        let mut monitor = app_context.waf.lock().unwrap();
+                        let observation =
+                            monitor.observe(&task, code, waf.is_some(), Arc::new(result));
+                        if observation.blocked.is_none() && app_context.paused() {
+                            // 暂停期间仍在进行中的请求被拦截，同样需要重新入队
+                            if let Some(task) = monitor.requeue_last() {
+                                task_queue.requeue([task]);
+                            }
+                        }
+                        observation
+                    };
+                    if let Some(blocked) = observation.blocked {
+                        let released = &mut observation.released;
+                        if handle_waf(&args, &app_context, &task_queue, waf, blocked, released) {
+                            task_queue.close();
+                        }
+                    }
+
+                    // 被 WAF 拦截后重新入队的任务不会交给 saver，避免重复记录
+                    for result in observation.released {
+                        let _ = result_channel.send(result).await;
+                    }
+                    succeeded = true;
                     break;
                 }
                 Err(e) => {
@@ -117,85 +290,600 @@ pub async fn worker(
                         c + 1,
                         e
                     );
+                    stats.record_error(&e);
+                    last_error = Some(e.to_string());
                 }
             };
         }
-        {
-            // 进度条加1
-            app_context
-                .lock()
-                .await
-                .pb
-                .as_ref()
-                .unwrap()
-                .instance
-                .inc(1);
+        let mut requeue = false;
+        if succeeded {
+            // 之前失败、重新入队后成功的任务
+            app_context.failures.lock().unwrap().failed.remove(&task);
+        } else {
+            // 记录重试全部失败的任务及最后一次的错误
+            let mut failures = app_context.failures.lock().unwrap();
+            failures
+                .failed
+                .insert(task.clone(), last_error.unwrap_or_default());
+            if args.canary_interval > 0 {
+                // canary 在持有同一个锁时修改 canary_down，保证任务不会遗漏
+                if app_context.canary_down.load(Ordering::SeqCst) {
+                    // 目标不可用期间失败的任务，重新入队
+                    requeue = true;
+                } else {
+                    // 交给 canary 判断失败是否由目标不可用导致
+                    failures.recent.push(task.clone());
+                }
+            }
+        }
+
+        // 完成的路径数加1，单个路径或者整个区间处理完成后，队列中的任务才算完成
+        progress.done.fetch_add(1, Ordering::Relaxed);
+        if requeue {
+            task_queue.requeue([task.clone()]);
+        }
+        if current.as_ref().is_none_or(KeyRange::is_empty) {
+            current = None;
+            if task_queue.task_done(task_id) {
+                task_queue.finish(&app_context);
+            }
+        }
+    }
+
+    app_context.stats.lock().unwrap().merge(stats);
+    // 最后一个退出的 worker 把 WAF 检测窗口中暂存的结果交给 saver
+    if progress.running_workers.fetch_sub(1, Ordering::SeqCst) == 1 {
+        let released = app_context.waf.lock().unwrap().release_all();
+        for result in released {
+            let _ = result_channel.send(result).await;
+        }
+    }
+    debug!("engine worker {} stop", idx);
+}
+
+/**
+ * 没有读取响应体时（例如 HEAD 方法），对状态码为 200 的命中结果再发送一次 GET 请求获取响应体
+ * 开启敏感内容检测时对所有命中结果生效，否则只对以 / 结尾、可能是目录列表的路径生效
+ * 响应体最多读取 MAX_HIT_BODY_SIZE 字节
+ */
+async fn fetch_hit_body(
+    http_client: &Client,
+    args: &AppArgs,
+    code: u16,
+    url: &str,
+    value: &str,
+) -> Option<String> {
+    if code != 200 || (args.no_detect && !url.ends_with('/')) {
+        return None;
+    }
+
+    let mut response = build_request(http_client, args, Method::GET, url, value)
+        .send()
+        .await
+        .ok()?;
+    let buffer = read_body(&mut response, args, MAX_HIT_BODY_SIZE).await;
+    Some(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/**
+ * 读取响应体，最多读取 limit 字节
+ * 指定了 read-timeout 时，两次收到数据之间超时则停止读取，返回已经读取到的内容
+ */
+pub async fn read_body(response: &mut Response, args: &AppArgs, limit: usize) -> Vec<u8> {
+    let mut buffer = vec![];
+    loop {
+        let chunk = match args.read_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, response.chunk()).await {
+                Ok(v) => v,
+                Err(_) => {
+                    debug!("read body timeout, url: {}", response.url());
+                    break;
+                }
+            },
+            None => response.chunk().await,
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                buffer.extend_from_slice(&chunk);
+                if buffer.len() >= limit {
+                    buffer.truncate(limit);
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                debug!("read body failed, url: {}, error: {}", response.url(), e);
+                break;
+            }
+        }
+    }
+    buffer
+}
+
+/**
+ * 解析目录列表中的条目，未超过最大深度时加入任务队列
+ */
+fn harvest_listing(
+    args: &AppArgs,
+    app_context: &AppContext,
+    task_queue: &TaskQueue,
+    task: &str,
+    content: &str,
+    url: &str,
+) {
+    // fuzz 模式下任务不是路径，目录列表中的条目无法作为任务，只标记不解析
+    if args.fuzz.enabled() {
+        return;
+    }
+    let mut harvested = app_context.harvested.lock().unwrap();
+    let depth = harvested.get(task).copied().unwrap_or(0) + 1;
+    if depth > args.listing_depth {
+        return;
+    }
+
+    let entries = listing::extract_entries(content, url, &args.target)
+        .into_iter()
+        .filter(|it| !harvested.contains_key(it))
+        .collect::<Vec<String>>();
+
+    // 跳过 builder 已经生成过的任务，枚举模式下直接判断是否属于 keyspace
+    let count = entries.len();
+    let entries = if args.dict_mode() {
+        entries
+            .into_iter()
+            .filter(|it| task_queue.first_seen(&seen_key(args, it)))
+            .collect::<Vec<String>>()
+    } else {
+        let keyspace = builder::enum_keyspace(args);
+        let lengths = builder::enum_lengths(args);
+        entries
+            .into_iter()
+            .filter(|it| !keyspace.contains(&lengths, it))
+            .collect::<Vec<String>>()
+    };
+    app_context
+        .stats
+        .lock()
+        .unwrap()
+        .record_duplicates((count - entries.len()) as u64);
+    if entries.is_empty() {
+        return;
+    }
+    info!("发现目录列表 {}，解析出 {} 个新条目", url, entries.len());
+
+    for entry in &entries {
+        harvested.insert(entry.clone(), depth);
+    }
+    task_queue.push(entries);
+}
+
+/**
+ * 触发 WAF 拦截后根据策略处理，被拦截的任务重新入队，返回 true 表示需要终止扫描
+ * 不重新入队时（ignore 和 abort 策略），被拦截的结果放入 released 交给 saver
+ */
+fn handle_waf(
+    args: &AppArgs,
+    app_context: &AppContext,
+    task_queue: &TaskQueue,
+    waf: Option<&'static str>,
+    blocked: Vec<(String, Arc<EnumResult>)>,
+    released: &mut Vec<Arc<EnumResult>>,
+) -> bool {
+    warn!(
+        "疑似被 WAF 拦截（{}），处理策略：{:?}",
+        waf.unwrap_or("响应分布突变"),
+        args.waf_policy
+    );
+
+    match args.waf_policy {
+        WafPolicy::Ignore => {
+            released.extend(blocked.into_iter().map(|it| it.1));
+            return false;
+        }
+        WafPolicy::Abort => {
+            error!("被 WAF 拦截，终止扫描");
+            released.extend(blocked.into_iter().map(|it| it.1));
+            app_context.aborted.store(true, Ordering::SeqCst);
+            return true;
+        }
+        WafPolicy::Pause => {}
+        WafPolicy::Rotate => {
+            // 持有写锁期间完成轮换，同时触发时不会重复轮换同一个序号
+            let mut clients = app_context.clients.write().unwrap();
+            let rotation = app_context.rotation.load(Ordering::SeqCst) + 1;
+            *clients = build_http_clients(args, rotation);
+            app_context.rotation.store(rotation, Ordering::SeqCst);
+            info!("轮换代理和 UA，第 {} 次", rotation);
         }
     }
 
-    app_context.lock().await.worker_status[idx] = WorkerStatus::Stop;
+    let tasks = {
+        let mut monitor = app_context.waf.lock().unwrap();
+        blocked
+            .into_iter()
+            .map(|it| it.0)
+            .filter(|it| monitor.mark_requeued(it))
+            .collect::<Vec<String>>()
+    };
+    info!(
+        "暂停扫描 {} 秒，{} 个任务重新入队",
+        args.waf_pause,
+        tasks.len()
+    );
+    app_context.pause(Duration::from_secs(args.waf_pause));
+    task_queue.requeue(tasks);
+    false
+}
+
+/**
+ * 提取 HTML 页面的标题，多个连续的空白字符合并为一个空格，最长保留 MAX_TITLE_LENGTH 个字符
+ */
+fn extract_title(content: &str) -> Option<String> {
+    // 只转换 ASCII 字符，保证下标和原文一致
+    let lower = content.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+
+    let title = content[start..end]
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.chars().take(MAX_TITLE_LENGTH).collect())
+    }
+}
+
+/**
+ * 拼接结果的描述，用于输出到终端和结果文件
+ * 格式为：状态码 URL fuzz=值 size=长度 type=类型 server=服务器 powered-by=技术栈 title="标题"，后面附带跳转、目录列表、敏感内容等信息
+ */
+fn describe_result(result: &EnumResult) -> String {
+    let mut describe = format!("{} {}", result.status_code, result.url);
+    if let Some(value) = &result.fuzz {
+        describe = format!("{} fuzz=\"{}\"", describe, value.replace('"', "'"));
+    }
+    describe = format!("{} size={}", describe, result.content_length);
+    let metas = [
+        ("type", CONTENT_TYPE.as_str()),
+        ("server", SERVER.as_str()),
+        ("powered-by", "x-powered-by"),
+    ];
+    for (name, header) in metas {
+        if let Some(value) = result.headers.get(header).and_then(|it| it.to_str().ok()) {
+            // 包含空格的值使用引号包裹
+            if value.contains(' ') {
+                describe = format!("{} {}=\"{}\"", describe, name, value.replace('"', "'"));
+            } else {
+                describe = format!("{} {}={}", describe, name, value);
+            }
+        }
+    }
+    if let Some(title) = &result.title {
+        describe = format!("{} title=\"{}\"", describe, title.replace('"', "'"));
+    }
+
+    // 有跳转时，把跳转链或跳转地址附在后面
+    if !result.redirects.is_empty() {
+        let chain = result
+            .redirects
+            .iter()
+            .map(|it| format!("{} -> {}", it.status_code, it.location))
+            .join(", ");
+        describe = format!("{} ({})", describe, chain);
+    } else if let Some(location) = &result.location {
+        describe = format!("{} (-> {})", describe, location);
+    }
+    if result.dir_listing {
+        describe = format!("{} [listing]", describe);
+    }
+    if let Some(severity) = result.findings.iter().map(|it| it.severity).max() {
+        let names = result.findings.iter().map(|it| it.name).join(", ");
+        describe = format!("{} [{}: {}]", describe, severity, names);
+    }
+    describe
+}
+
+/**
+ * 拼接响应的状态行，例如 HTTP/1.1 200 OK
+ */
+fn get_status_line(response: &Response) -> String {
+    let status = response.status();
+    format!(
+        "{:?} {} {}",
+        response.version(),
+        status.as_str(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+/**
+ * 构建所有 worker 共用的 HTTP Client，数量由 client-count 指定
+ */
+pub fn build_http_clients(args: &AppArgs, rotation: usize) -> Vec<Client> {
+    (0..args.client_count)
+        .map(|_| build_http_client(args, rotation))
+        .collect()
+}
+
+/**
+ * 获取 worker 使用的 HTTP Client，worker 依次分配到不同的 Client 上
+ * Client 内部通过 Arc 共享连接池，clone 的开销很小
+ */
+pub fn get_http_client(app_context: &AppContext, idx: usize) -> Client {
+    let clients = app_context.clients.read().unwrap();
+    clients[idx % clients.len()].clone()
+}
+
+/**
+ * 根据 CLI 参数构建 HTTP Client
+ * rotation 为代理和 UA 的轮换次数，大于 0 时依次使用下一个代理，并随机选择一个 UA
+ */
+pub fn build_http_client(args: &AppArgs, rotation: usize) -> Client {
+    // 如果没使用 random user agent，直接在这里把UA写进去
+    // 跳转由 send_with_redirects 手动处理，便于记录跳转链
+    let mut builder = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .redirect(Policy::none())
+        .pool_idle_timeout(args.pool_idle_timeout)
+        .tcp_keepalive(args.tcp_keepalive)
+        .tcp_nodelay(args.tcp_nodelay);
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(timeout) = args.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(max) = args.pool_max_idle {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if !args.random_user_agent {
+        if rotation > 0 {
+            let random_ua = args.user_agent_list.choose(&mut rand::thread_rng());
+            builder = builder.user_agent(random_ua.unwrap());
+        } else {
+            builder = builder.user_agent(&args.user_agent);
+        }
+    }
+
+    // vhost 模式下所有主机名都连接到 target 的地址
+    if args.fuzz.host {
+        builder = builder.dns_resolver(Arc::new(FixedResolver::new(args.vhost_addrs.clone())));
+    }
+
+    // 如果在CLI参数中指定了代理，则把代理设置进去，默认对 http/https 协议都生效
+    if !args.proxies.is_empty() {
+        let proxy = &args.proxies[rotation % args.proxies.len()];
+        let _proxy = reqwest::Proxy::all(proxy);
+        if _proxy.is_err() {
+            error!("代理设置错误！");
+            exit(-1);
+        }
+        builder = builder.proxy(_proxy.unwrap());
+    }
+
+    builder.build().unwrap()
+}
+
+/**
+ * 构建单个请求，附带 CLI 参数中指定的 UA、header、cookie 和请求体，其中的 FUZZ 标记替换为 value
+ */
+pub fn build_request(
+    http_client: &Client,
+    args: &AppArgs,
+    method: Method,
+    url: &str,
+    value: &str,
+) -> RequestBuilder {
+    // 如果使用了 random-user-agent 选项，就随机一个 agent 出来，然后塞到头里
+    let mut request = http_client.request(method.clone(), url);
+    if args.random_user_agent {
+        let random_ua = args.user_agent_list.choose(&mut rand::thread_rng());
+        request = request.header("User-Agent", random_ua.unwrap());
+    }
+
+    // 如果在 CLI 参数中设置了 header 则依次添加
+    for header in &args.headers {
+        let header_part = header.splitn(2, ':').collect::<Vec<&str>>();
+
+        // 跳过不合法的header
+        if header_part.len() < 2 {
+            continue;
+        }
+
+        let key = header_part[0].trim();
+        let header_value = header_part[1].trim();
+
+        if args.fuzz.headers {
+            request = request.header(key, fuzz::substitute(header_value, value));
+        } else {
+            request = request.header(key, header_value);
+        }
+    }
+
+    // 如果在 CLI 参数中设置了 cookie 则添加一个 cookie 头
+    if let Some(cookie) = &args.cookies {
+        if args.fuzz.cookie {
+            request = request.header("Cookie", fuzz::substitute(cookie, value));
+        } else {
+            request = request.header("Cookie", cookie);
+        }
+    }
+
+    // HEAD 请求不携带请求体
+    if let Some(body) = &args.body {
+        if method != Method::HEAD {
+            if args.fuzz.body {
+                request = request.body(fuzz::substitute(body, value));
+            } else {
+                request = request.body(body.clone());
+            }
+        }
+    }
+
+    request
+}
+
+/**
+ * 定期根据扫描进度刷新进度条，任务队列关闭后退出
+ */
+pub async fn reporter(pb: EnumProgressBar, progress: Arc<Progress>, task_queue: Arc<TaskQueue>) {
+    loop {
+        pb.render(&progress);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+            _ = task_queue.closed() => break,
+        }
+    }
+    pb.render(&progress);
+}
+
+/**
+ * 与输出文件同名的附属文件路径，例如 result.txt -> result.errors.txt、result.stats.json
+ */
+fn get_side_output(output: &str, name: &str, extension: &str) -> String {
+    let stem = output.strip_suffix(".txt").unwrap_or(output);
+    format!("{}.{}.{}", stem, name, extension)
 }
 
 pub async fn saver(
-    app_context: Arc<Mutex<AppContext>>,
+    app_context: Arc<AppContext>,
     args: Arc<AppArgs>,
     result_channel: Receiver<Arc<EnumResult>>,
+    pb: EnumProgressBar,
+    progress: Arc<Progress>,
 ) {
     let output = &args.output;
     let mut output_file_handler = File::create(output).await.unwrap();
     let black_re = args.black_words.as_ref().map(|bw| Regex::new(bw).unwrap());
+    let mut deduplicator = Deduplicator::new(args.dedup_threshold);
+    // 已经写入的结果及其聚类情况，用于扫描结束后重写被折叠的聚类
+    let mut written: Vec<(Verdict, String)> = vec![];
 
-    loop {
-        let result = result_channel.try_recv();
-        if let Ok(result) = result {
-            if result.status_code == 404 {
+    // 所有 worker 退出后结果通道的发送端全部被释放，取完剩余的结果后 recv 返回错误，saver 随之退出
+    while let Ok(result) = result_channel.recv().await {
+        if result.status_code == 404 || result.soft_404 || result.waf.is_some() {
+            continue;
+        }
+
+        // 如果有设置 black_words 并且有 content，就在这里过滤
+        if let (Some(black_re), Some(content)) = (&black_re, &result.content) {
+            // 如果 match 了黑名单，就跳过这条结果
+            if black_re.is_match(content.as_str()) {
                 continue;
             }
 
-            // 如果有设置 black_words 并且有 content，就在这里过滤
-            if let (Some(black_re), Some(content)) = (&black_re, &result.content) {
-                // 如果 match 了黑名单，就跳过这条结果
-                if black_re.is_match(content.as_str()) {
-                    continue;
-                }
-
-                if args.debug_mode {
-                    debug!("black_re: {}, content: {}", black_re, content);
-                }
+            if args.debug_mode {
+                debug!("black_re: {}, content: {}", black_re, content);
             }
+        }
+
+        // 根据 match / filter 规则过滤
+        if !args.rules.accept(&result) {
+            continue;
+        }
 
-            // info!("Found {} {}", result.status_code, result.url);
-            {
-                app_context
-                    .lock()
-                    .await
-                    .pb
-                    .as_ref()
-                    .unwrap()
-                    .instance
-                    .println(format!("Found {} {}", result.status_code, result.url));
+        // 根据过滤表达式过滤
+        if let Some(expr) = &args.filter_expr {
+            if !expr.eval(&result) {
+                continue;
             }
+        }
 
-            let line = format!("{} {}\n", result.status_code, result.url);
-            let _ = output_file_handler
-                .write(line.as_bytes().as_ref())
-                .await
-                .unwrap();
-        } else if !app_context
-            .lock()
-            .await
-            .worker_status
-            .contains(&WorkerStatus::Running)
-        {
-            break;
-        } else {
-            tokio::time::sleep(Duration::from_millis(500)).await;
+        // 相同或相似的响应过多时折叠
+        let verdict = deduplicator.check(&result);
+        if verdict == Verdict::Collapsed {
             continue;
         }
+
+        let describe = describe_result(&result);
+
+        // info!("Found {} {}", result.status_code, result.url);
+        progress.found.fetch_add(1, Ordering::Relaxed);
+        pb.instance.println(if result.head_fallback {
+            format!("Found {} (HEAD->GET)", describe)
+        } else {
+            format!("Found {}", describe)
+        });
+
+        let line = format!("{}\n", describe);
+        let _ = output_file_handler
+            .write(line.as_bytes().as_ref())
+            .await
+            .unwrap();
+        written.push((verdict, line));
+    }
+
+    // 有聚类被折叠时重写结果文件，每个被折叠的聚类只在第一个结果的位置保留一条汇总记录
+    if deduplicator.collapsed() {
+        let mut content = String::new();
+        let mut summarized = HashSet::new();
+        for (verdict, line) in &written {
+            let summary = match verdict {
+                Verdict::Member(idx) => deduplicator.summary(*idx).map(|it| (*idx, it)),
+                _ => None,
+            };
+            match summary {
+                Some((idx, summary)) => {
+                    if summarized.insert(idx) {
+                        info!("Collapsed {}", summary);
+                        content.push_str(&summary);
+                        content.push('\n');
+                    }
+                }
+                None => content.push_str(line),
+            }
+        }
+        // 等待已经提交的写入完成后再覆盖文件
+        let _ = output_file_handler.flush().await;
+        drop(output_file_handler);
+        if let Err(e) = tokio::fs::write(output, content).await {
+            error!("写入 {} 失败，error: {}", output, e);
+        }
+    }
+
+    pb.render(&progress);
+    pb.instance.finish();
+    // 仍然失败的任务写入 errors 文件，格式与字典一致，可以直接作为字典再次扫描
+    let failed = std::mem::take(&mut app_context.failures.lock().unwrap().failed);
+    if !failed.is_empty() {
+        let errors_output = get_side_output(output, "errors", "txt");
+        let mut content = String::from("# 以下任务的请求全部失败，注释行为最后一次的错误\n");
+        for (task, error) in failed.iter().sorted() {
+            content.push_str(&format!("# {}\n{}\n", error, task));
+        }
+        match tokio::fs::write(&errors_output, content).await {
+            Ok(_) => warn!(
+                "共有 {} 个任务请求失败，已写入 {}",
+                failed.len(),
+                errors_output
+            ),
+            Err(e) => error!("写入 {} 失败，error: {}", errors_output, e),
+        }
+    }
+
+    // 输出统计汇总，并写入结构化的统计文件
+    let (summary, stats) = {
+        let stats = app_context.stats.lock().unwrap();
+        (stats.summary(), stats.to_json())
+    };
+    for line in summary {
+        println!("{}", line);
+    }
+    let stats_output = get_side_output(output, "stats", "json");
+    let stats = serde_json::to_string_pretty(&stats).unwrap();
+    if let Err(e) = tokio::fs::write(&stats_output, stats).await {
+        error!("写入 {} 失败，error: {}", stats_output, e);
+    }
+
+    let head_fallback_count = app_context.head_fallback_count.load(Ordering::Relaxed);
+    if head_fallback_count > 0 {
+        info!(
+            "共有 {} 个请求被服务端拒绝 HEAD 方法，已自动回退为 GET",
+            head_fallback_count
+        );
     }
-    let mut guard = app_context.lock().await;
-    guard.saver_status = WorkerStatus::Stop;
-    guard.pb.as_ref().unwrap().instance.finish();
-    // app_context.lock().await.saver_status = WorkerStatus::Stop;
     info!("Save worker stop.");
 }
@@ -0,0 +1,161 @@
+use crate::args_parser::AppArgs;
+use crate::context::AppContext;
+use crate::engines::dedup::{self, SIMHASH_DISTANCE};
+use crate::engines::fuzz;
+use crate::engines::read_body;
+use crate::engines::redirect::{get_location, send_with_redirects};
+use log::{debug, warn};
+use reqwest::{Client, Method};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+// 获取基准页面时，响应体最多读取的字节数
+const MAX_BASELINE_BODY_SIZE: usize = 1024 * 1024;
+
+// 缓存中的基准页面，第一个 worker 请求时初始化，获取失败时为 None
+pub type BaselineCell = Arc<OnceCell<Option<Baseline>>>;
+
+/**
+ * 某个目录下不存在页面的响应特征，用于识别 soft-404
+ */
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    pub status_code: u16,
+    pub content_length: u64,
+    pub simhash: Option<u64>,
+    pub location: Option<String>,
+}
+
+impl Baseline {
+    /**
+     * 判断结果是否与基准页面一致，一致时认为是 soft-404
+     * 有响应体时比较 simhash，否则比较跳转地址和响应长度
+     */
+    pub fn matches(
+        &self,
+        status_code: u16,
+        simhash: Option<u64>,
+        location: Option<&String>,
+        content_length: u64,
+    ) -> bool {
+        if status_code != self.status_code {
+            return false;
+        }
+
+        if let (Some(a), Some(b)) = (simhash, self.simhash) {
+            return (a ^ b).count_ones() <= SIMHASH_DISTANCE;
+        }
+
+        match (location, &self.location) {
+            (Some(a), Some(b)) => strip_query(a) == strip_query(b),
+            (None, None) => content_length == self.content_length,
+            _ => false,
+        }
+    }
+}
+
+fn strip_query(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+/**
+ * 基准页面的缓存 key，由任务所在的目录和后缀组成，例如 api/index.php -> (api/, .php)
+ */
+pub fn baseline_key(task: &str) -> (String, String) {
+    let (dir, name) = match task.trim_end_matches('/').rfind('/') {
+        Some(idx) => task.split_at(idx + 1),
+        None => ("", task),
+    };
+
+    let suffix = if name.ends_with('/') {
+        "/"
+    } else {
+        // 以 . 开头的文件（例如 .env）不认为有后缀，tar.gz 这样的多段后缀整体保留
+        match name.trim_start_matches('.').find('.') {
+            Some(idx) => &name[name.len() - name.trim_start_matches('.').len() + idx..],
+            None => "",
+        }
+    };
+    (dir.to_owned(), suffix.to_owned())
+}
+
+/**
+ * 获取任务所在目录的基准页面，第一次使用时请求一个随机的不存在路径生成，之后从缓存中读取
+ * 多个 worker 同时获取同一个目录的基准页面时只发送一次请求，其余的 worker 等待结果
+ */
+pub async fn get_baseline(
+    http_client: &Client,
+    args: &AppArgs,
+    app_context: &AppContext,
+    task: &str,
+) -> Option<Baseline> {
+    // fuzz 模式下任务不是路径，所有任务共用一个基准页面
+    let key = if args.fuzz.enabled() {
+        (String::new(), String::new())
+    } else {
+        baseline_key(task)
+    };
+    let cached = app_context.baselines.read().unwrap().get(&key).cloned();
+    let cell = match cached {
+        Some(v) => v,
+        None => {
+            let mut baselines = app_context.baselines.write().unwrap();
+            Arc::clone(baselines.entry(key.clone()).or_default())
+        }
+    };
+    cell.get_or_init(|| fetch_baseline(http_client, args, &key))
+        .await
+        .clone()
+}
+
+/**
+ * 请求 key 对应目录下一个随机的不存在路径，生成基准页面，请求失败时返回 None
+ */
+async fn fetch_baseline(
+    http_client: &Client,
+    args: &AppArgs,
+    key: &(String, String),
+) -> Option<Baseline> {
+    let random_name = format!("{}{}{}", key.0, fuzz::random_value(args), key.1);
+    let url = fuzz::request_url(args, &random_name);
+
+    // 指定了请求体时与任务使用相同的方法，否则使用 GET 方法获取响应体
+    let method = match &args.body {
+        Some(_) => Method::from_bytes(args.request_method.as_bytes()).unwrap_or(Method::GET),
+        None => Method::GET,
+    };
+    match send_with_redirects(http_client, args, method, &url, &random_name).await {
+        Ok((mut response, redirects)) => {
+            let status_code = response.status().as_u16();
+            let location = get_location(&response)
+                .map(|it| it.to_string())
+                .or_else(|| redirects.last().map(|it| it.location.clone()));
+
+            let buffer = read_body(&mut response, args, MAX_BASELINE_BODY_SIZE).await;
+            let content = String::from_utf8_lossy(&buffer);
+            let simhash = if content.is_empty() {
+                None
+            } else {
+                Some(dedup::fingerprint(&content, &url).1)
+            };
+
+            debug!(
+                "baseline for {}*{}: status {}, length {}",
+                key.0,
+                key.1,
+                status_code,
+                buffer.len()
+            );
+            Some(Baseline {
+                status_code,
+                content_length: buffer.len() as u64,
+                simhash,
+                location,
+            })
+        }
+        Err(e) => {
+            warn!("获取基准页面失败，url: {}, error: {}", url, e);
+            None
+        }
+    }
+}
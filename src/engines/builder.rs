@@ -1,37 +1,77 @@
 use crate::args_parser::AppArgs;
-use crate::context::{AppContext, EnumProgressBar};
-use crate::WorkerStatus;
-use async_channel::Sender;
+use crate::context::AppContext;
+use crate::engines::keyspace::{KeyRange, Keyspace};
+use crate::engines::queue::{Task, TaskQueue};
+use crate::engines::seen::seen_key;
 use itertools::Itertools;
 use log::{debug, error, info, warn};
-use std::collections::HashMap;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::ops::RangeInclusive;
 use std::path::Path;
 use std::process::exit;
 use std::sync::Arc;
 use std::vec;
 use tokio::fs::read_to_string;
-use tokio::sync::Mutex;
+
+// 枚举模式下每个区间包含的任务数
+const RANGE_SIZE: u64 = 256;
+// 字典模式下调整顺序时最多缓存的任务数
+const ORDER_WINDOW: usize = 65536;
 
 /**
- * 通过迭代器生成待枚举的文件名，并放到 channel 中
+ * 任务的生成顺序
  */
-pub async fn builder(
-    task_channel: Sender<String>,
-    args: Arc<AppArgs>,
-    app_context: Arc<Mutex<AppContext>>,
-) {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TaskOrder {
+    // 按字典序或字典中的顺序
+    #[default]
+    Lexical,
+    // 由种子决定的伪随机顺序
+    Random,
+    // 在不同的目录之间轮流生成
+    Interleave,
+}
+
+impl TaskOrder {
+    pub fn parse(raw: &str) -> Result<Self, &'static str> {
+        match raw.to_lowercase().as_str() {
+            "lexical" => Ok(TaskOrder::Lexical),
+            "random" => Ok(TaskOrder::Random),
+            "interleave" => Ok(TaskOrder::Interleave),
+            _ => Err("order 只能为 lexical、random 或 interleave！"),
+        }
+    }
+}
+
+/**
+ * 通过迭代器生成待枚举的文件名，并放到任务队列中
+ */
+pub async fn builder(task_queue: Arc<TaskQueue>, args: Arc<AppArgs>, app_context: Arc<AppContext>) {
     // 先根据命令行参数，判断使用字典模式还是枚举模式
-    // 如果 dict_path 不为 None，则使用字典模式，否则使用枚举模式
-    if args.dict_path.is_some() {
+    // 指定了字典或者字典组合模板时使用字典模式，否则使用枚举模式
+    if args.dict_mode() {
         // 字典模式
-        dict_builder(task_channel, &args, &app_context).await;
+        let duplicates = dict_builder(&task_queue, &args).await;
+        if duplicates > 0 {
+            info!("跳过了 {} 个重复的任务", duplicates);
+            app_context
+                .stats
+                .lock()
+                .unwrap()
+                .record_duplicates(duplicates);
+        }
     } else {
-        // 枚举模式
-        enum_builder(task_channel, &args, &app_context).await;
+        // 枚举模式，keyspace 中的任务不会重复
+        enum_builder(&task_queue, &args).await;
     }
 
-    app_context.lock().await.builder_status = WorkerStatus::Stop;
     info!("builder end!");
+    if task_queue.builder_done() {
+        task_queue.finish(&app_context);
+    }
 }
 
 fn get_suffix_from_cli(args: &AppArgs) -> Vec<String> {
@@ -40,21 +80,21 @@ fn get_suffix_from_cli(args: &AppArgs) -> Vec<String> {
         suffixes.push("".to_owned());
         suffixes.push("/".to_owned())
     }
-    args.suffix
-        .split(',')
-        .for_each(|it| suffixes.push(format!(".{}", it.trim())));
+    // 重复的后缀只保留一个
+    for suffix in args.suffix.split(',') {
+        let suffix = format!(".{}", suffix.trim());
+        if !suffixes.contains(&suffix) {
+            suffixes.push(suffix);
+        }
+    }
     debug!("suffixes: {:?}", suffixes);
     suffixes
 }
 
 /**
- * 枚举模式生产任务
+ * 根据命令行参数构建枚举模式的 keyspace，参数一致时不同进程构建出的 keyspace 相同
  */
-async fn enum_builder(
-    task_channel: Sender<String>,
-    args: &AppArgs,
-    app_context: &Arc<Mutex<AppContext>>,
-) {
+pub fn enum_keyspace(args: &AppArgs) -> Keyspace {
     // 处理 suffix
     let suffixes = get_suffix_from_cli(args);
 
@@ -64,83 +104,123 @@ async fn enum_builder(
         .chain('0'..='9')
         .collect::<Vec<_>>();
 
+    // 随机顺序时对 keyspace 的编号做伪随机排列
+    let seed = match args.order {
+        TaskOrder::Random => Some(args.seed),
+        _ => None,
+    };
+    Keyspace::new(pool, suffixes).with_seed(seed)
+}
+
+/**
+ * 枚举模式下生成的文件名长度范围
+ */
+pub fn enum_lengths(args: &AppArgs) -> RangeInclusive<usize> {
     let max_length = args.length;
-    let suffix_length = suffixes.len();
-    let pool_length = pool.len();
-    let range = if args.fixed_length {
+    if args.fixed_length {
         max_length..=max_length
     } else {
         1..=max_length
-    };
+    }
+}
 
-    // 计算待生成的总任务数，放到 app_context.pb 中
+/**
+ * 枚举模式生产任务
+ */
+async fn enum_builder(task_queue: &TaskQueue, args: &AppArgs) {
+    let range = enum_lengths(args);
+    let keyspace = Arc::new(enum_keyspace(args));
+
+    // 计算当前分片待生成的总任务数，用于展示进度
     let mut total: u64 = 0;
     for t in range.clone() {
-        total += (pool_length.pow(t as u32) * suffix_length) as u64;
-    }
-    let enum_pb = EnumProgressBar::new(total);
-    {
-        app_context.lock().await.pb = Some(enum_pb);
+        let slice = args.shard.slice(keyspace.size(t));
+        total += slice.end - slice.start;
     }
+    task_queue.expect(total);
 
-    // 按照预定长度生成枚举字符串，并放到 channel 中
-    let mut current_length = 1;
-    for idx in range {
-        let product = (1..=idx).map(|_| pool.iter()).multi_cartesian_product();
-        for it in product {
-            if idx != current_length {
-                info!("length {} build done.", current_length);
-                current_length = idx;
-            }
-            for s in &suffixes {
-                // let path_name = it.iter().map(|&x| x).join("");
-                let path_name = it.iter().cloned().join("");
-                let task = format!("{}{}", path_name, s);
-                // debug!("task: {}", task);
-                let result = task_channel.send(task.clone()).await;
-                if result.is_err() {
-                    warn!(
-                        "Error put task to channel, task: {}, error: {:?}",
-                        task,
-                        result.unwrap_err().0
-                    );
-                }
+    // 按照预定长度把 keyspace 中属于当前分片的部分切分成区间放到队列中，由 worker 在本地渲染出具体的路径
+    for length in range {
+        let slice = args.shard.slice(keyspace.size(length));
+        for start in slice.clone().step_by(RANGE_SIZE as usize) {
+            let range = KeyRange {
+                keyspace: Arc::clone(&keyspace),
+                length,
+                start,
+                end: (start + RANGE_SIZE).min(slice.end),
+            };
+            // 队列被关闭说明扫描已被终止
+            if task_queue.send(Task::Range(range)).await.is_none() {
+                warn!(
+                    "Error put task to queue, length: {}, start: {}",
+                    length, start
+                );
+                return;
             }
         }
+        info!("length {} build done.", length);
     }
-    info!("length {} build done.", current_length);
 }
 
 /**
- * 字典模式生产任务
+ * 字典模式生产任务，指定了模板时按模板组合多个字典，返回跳过的重复任务数量
  */
-async fn dict_builder(
-    task_channel: Sender<String>,
-    args: &AppArgs,
-    app_context: &Arc<Mutex<AppContext>>,
-) {
+async fn dict_builder(task_queue: &TaskQueue, args: &AppArgs) -> u64 {
+    let pools = build_pools(get_suffix_from_cli(args), args.root_domain.as_ref());
+
+    // 组合多个字典，组合结果在生成任务时逐个渲染
+    if let Some(combination) = &args.combination {
+        let combinations = combination.load().await;
+        let total = combinations.total(|line| line_total(line, &pools));
+        return emit_lines(task_queue, args, &pools, total, combinations).await;
+    }
+
     // 如果这里不提前定义 dict_content 变量，后面的 else 分支会出现悬垂引用，暂时想不到更优雅的方案了
     let dict_content: String;
     let dict_path = args.dict_path.as_ref().unwrap().as_str();
     let dict_lines = if dict_path.is_empty() || !Path::new(dict_path).exists() {
         info!("未指定字典文件或文件不存在，切换到内置字典...");
-        include_str!("../../dicts/default.txt").lines()
+        if args.fuzz.host {
+            include_str!("../../dicts/vhost.txt").lines()
+        } else {
+            include_str!("../../dicts/default.txt").lines()
+        }
     } else {
         // 从文件读
-        let read_result = read_to_string(dict_path).await;
-        if read_result.is_err() {
-            error!("读取字典文件出错，错误：{:?}", read_result.unwrap_err());
-            exit(-1);
-        }
-        dict_content = read_result.unwrap();
+        dict_content = match read_to_string(dict_path).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("读取字典文件出错，错误：{:?}", e);
+                exit(-1);
+            }
+        };
         dict_content.lines()
     };
+    // 跳过空行和注释行
+    let dict_lines = dict_lines.filter(|line| !line.is_empty() && !line.starts_with('#'));
 
-    // 获取 suffixes
-    let suffixes = get_suffix_from_cli(args);
-    let suffixes_count = suffixes.len();
+    // 预先计算总任务数量
+    let total: u64 = dict_lines
+        .clone()
+        .map(|line| line_total(line, &pools))
+        .sum();
+    emit_lines(
+        task_queue,
+        args,
+        &pools,
+        total,
+        dict_lines.map(|line| line.to_owned()),
+    )
+    .await
+}
 
-    // 为 pattern 构建 pool
+/**
+ * 为 pattern 构建 pool，vhost 模式下 %ROOTDOMAIN% 替换为根域名
+ */
+fn build_pools(
+    suffixes: Vec<String>,
+    root_domain: Option<&String>,
+) -> HashMap<&'static str, Vec<String>> {
     let mut pools = HashMap::new();
     pools.insert(
         "%ALPHA%",
@@ -164,45 +244,52 @@ async fn dict_builder(
             .collect::<Vec<String>>(),
     );
     pools.insert("%EXT%", suffixes);
-
-    // 预先计算总任务数量
-    let mut total: u64 = 0;
-    for line in dict_lines.clone() {
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
-        // 简单的判断每种pattern分别出现了几次即可
-        let alpha_pattern_count = line.matches("%ALPHA%").count();
-        let number_pattern_count = line.matches("%NUMBER%").count();
-        let alpha_number_pattern_count = line.matches("%ALPHANUM%").count();
-        let ext_pattern_count = line.matches("%EXT%").count();
-        // 计算当前行展开后可以变成多少个任务
-        let mut buf: Vec<u64> = vec![1];
-        buf.resize(buf.len() + alpha_pattern_count, 52);
-        buf.resize(buf.len() + number_pattern_count, 10);
-        buf.resize(buf.len() + alpha_number_pattern_count, 62);
-        buf.resize(buf.len() + ext_pattern_count, suffixes_count as u64);
-        let line_total: u64 = buf.into_iter().filter(|&it| it != 0).product::<u64>();
-        total += line_total;
+    if let Some(root) = root_domain {
+        pools.insert("%ROOTDOMAIN%", vec![root.to_owned()]);
     }
+    pools
+}
 
-    // 设置进度条
-    let enum_pb = EnumProgressBar::new(total);
-    {
-        app_context.lock().await.pb = Some(enum_pb);
-    }
+/**
+ * 计算一行展开后可以变成多少个任务
+ */
+fn line_total(line: &str, pools: &HashMap<&'static str, Vec<String>>) -> u64 {
+    // 简单的判断每种pattern分别出现了几次即可
+    pools
+        .iter()
+        .filter(|(_, pool)| !pool.is_empty())
+        .map(|(pat, pool)| (pool.len() as u64).saturating_pow(line.matches(pat).count() as u32))
+        .fold(1u64, |total, it| total.saturating_mul(it))
+}
 
-    for line in dict_lines {
-        // 跳过空行和注释行
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
+/**
+ * 展开每一行中的占位符，跳过等价写法重复的任务，按分片和顺序放入队列，返回跳过的数量
+ */
+async fn emit_lines(
+    task_queue: &TaskQueue,
+    args: &AppArgs,
+    pools: &HashMap<&'static str, Vec<String>>,
+    total: u64,
+    lines: impl Iterator<Item = String>,
+) -> u64 {
+    // 设置进度条的总任务数，分片时只计算属于当前分片的任务
+    let expected = args.shard.count_of(total);
+    task_queue.expect(expected);
+    // 按展开后的任务数量准备去重使用的过滤器
+    task_queue.reserve_seen(total);
 
-        // 如果字典中的某一项是以 / 开头的，则去掉 / 符号
-        let item = if line.starts_with('/') {
-            line.trim_start_matches('/')
+    // 去重后任务的编号，用于分片
+    let mut index: u64 = 0;
+    let mut owned: u64 = 0;
+    let mut duplicates: u64 = 0;
+    // 非字典顺序时在有限大小的窗口中调整顺序
+    let mut reorder = Reorder::new(args.order, args.seed);
+    for line in lines {
+        // 如果字典中的某一项是以 / 开头的，则去掉 / 符号，fuzz 模式下任务是替换标记的值，原样保留
+        let item = if args.fuzz.enabled() {
+            line.as_str()
         } else {
-            line
+            line.trim_start_matches('/')
         };
 
         let line_parts = get_line_part(item);
@@ -234,14 +321,119 @@ async fn dict_builder(
 
         // debug!("tasks: {:?}, line: {}", tasks, line);
         for task in tasks {
-            if let Err(e) = task_channel.send(task.clone()).await {
-                warn!(
-                    "Error put task to channel, line: {}, task: {}, error: {:?}",
-                    line, task, e
-                );
+            if !task_queue.first_seen(&seen_key(args, &task)) {
+                duplicates += 1;
+                continue;
             }
+            index += 1;
+            if !args.shard.owns(index - 1) {
+                continue;
+            }
+            owned += 1;
+            for task in reorder.push(task) {
+                // 队列被关闭说明扫描已被终止
+                if task_queue.send(Task::Path(task.clone())).await.is_none() {
+                    warn!("Error put task to queue, line: {}, task: {}", line, task);
+                    return duplicates;
+                }
+            }
+        }
+    }
+    // 跳过的重复任务不计入总数
+    task_queue.expect_fewer(expected.saturating_sub(owned));
+
+    for task in reorder.drain() {
+        // 队列被关闭说明扫描已被终止
+        if task_queue.send(Task::Path(task.clone())).await.is_none() {
+            warn!("Error put task to queue, task: {}", task);
+            break;
         }
     }
+    duplicates
+}
+
+/**
+ * 在最多 ORDER_WINDOW 个任务的窗口中调整任务顺序，避免把展开后的全部任务放在内存中
+ * random 每次从窗口中随机取出一个任务，interleave 在窗口填满时按目录轮流取出整个窗口
+ */
+struct Reorder {
+    order: TaskOrder,
+    rng: StdRng,
+    window: Vec<String>,
+}
+
+impl Reorder {
+    fn new(order: TaskOrder, seed: u64) -> Self {
+        Reorder {
+            order,
+            rng: StdRng::seed_from_u64(seed),
+            window: vec![],
+        }
+    }
+
+    /**
+     * 放入一个任务，返回可以放入队列的任务
+     */
+    fn push(&mut self, task: String) -> Vec<String> {
+        if self.order == TaskOrder::Lexical {
+            return vec![task];
+        }
+        self.window.push(task);
+        if self.window.len() < ORDER_WINDOW {
+            return vec![];
+        }
+        match self.order {
+            TaskOrder::Random => {
+                let idx = self.rng.gen_range(0..self.window.len());
+                vec![self.window.swap_remove(idx)]
+            }
+            _ => interleave(std::mem::take(&mut self.window)),
+        }
+    }
+
+    /**
+     * 所有任务都已放入，取出窗口中剩余的任务
+     */
+    fn drain(mut self) -> Vec<String> {
+        match self.order {
+            TaskOrder::Random => {
+                self.window.shuffle(&mut self.rng);
+                self.window
+            }
+            _ => interleave(self.window),
+        }
+    }
+}
+
+/**
+ * 按任务所在的目录分组，在各个目录之间轮流取出任务，同一目录中的任务保持原来的顺序
+ */
+fn interleave(tasks: Vec<String>) -> Vec<String> {
+    let mut groups: Vec<VecDeque<String>> = vec![];
+    let mut group_index: HashMap<String, usize> = HashMap::new();
+    for task in tasks {
+        let base = match task.trim_end_matches('/').rfind('/') {
+            Some(idx) => task[..idx].to_owned(),
+            None => String::new(),
+        };
+        let idx = *group_index.entry(base).or_insert_with(|| {
+            groups.push(VecDeque::new());
+            groups.len() - 1
+        });
+        groups[idx].push_back(task);
+    }
+
+    let mut result = vec![];
+    while !groups.is_empty() {
+        groups.retain_mut(|group| match group.pop_front() {
+            Some(task) => {
+                result.push(task);
+                true
+            }
+            None => false,
+        });
+    }
+    result
 }
 
 /**
@@ -288,3 +480,78 @@ fn get_line_part(line: &str) -> Vec<String> {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Progress;
+    use std::collections::HashSet;
+
+    fn reorder_all(order: TaskOrder, tasks: &[String]) -> Vec<String> {
+        let mut reorder = Reorder::new(order, 42);
+        let mut result = vec![];
+        for task in tasks {
+            result.extend(reorder.push(task.to_owned()));
+            assert!(reorder.window.len() < ORDER_WINDOW);
+        }
+        result.extend(reorder.drain());
+        result
+    }
+
+    #[test]
+    fn reorder_keeps_every_task_once() {
+        let tasks: Vec<String> = (0..ORDER_WINDOW * 2 + 7)
+            .map(|i| format!("d{}/{}", i / 1000 % 3, i))
+            .collect();
+        for order in [TaskOrder::Lexical, TaskOrder::Random, TaskOrder::Interleave] {
+            let result = reorder_all(order, &tasks);
+            assert_eq!(result.len(), tasks.len());
+            assert_eq!(
+                result.iter().collect::<HashSet<_>>(),
+                tasks.iter().collect::<HashSet<_>>()
+            );
+            assert_eq!(result == tasks, order == TaskOrder::Lexical);
+        }
+        // 相同的种子得到相同的顺序
+        assert_eq!(
+            reorder_all(TaskOrder::Random, &tasks),
+            reorder_all(TaskOrder::Random, &tasks)
+        );
+    }
+
+    #[tokio::test]
+    async fn emit_keeps_original_task() {
+        let args = AppArgs {
+            target: "http://127.0.0.1/".to_owned(),
+            ..Default::default()
+        };
+        let task_queue = TaskQueue::new(16, Arc::new(Progress::default()));
+        let lines = ["..//admin", "%2fetc", "a//b", "a/b", "./x", "/x"];
+        let duplicates = emit_lines(
+            &task_queue,
+            &args,
+            &HashMap::new(),
+            lines.len() as u64,
+            lines.iter().map(|it| it.to_string()),
+        )
+        .await;
+        task_queue.close();
+
+        let mut emitted = vec![];
+        while let Some((_, Task::Path(task))) = task_queue.recv().await {
+            emitted.push(task);
+        }
+        // 统一写法只用于去重，发送的任务保持原样
+        assert_eq!(emitted, ["..//admin", "%2fetc", "a//b", "./x"]);
+        assert_eq!(duplicates, 2);
+    }
+
+    #[test]
+    fn interleave_rotates_directories() {
+        let tasks: Vec<String> = ["a/1", "a/2", "b/1", "b/2", "c"]
+            .iter()
+            .map(|it| it.to_string())
+            .collect();
+        assert_eq!(interleave(tasks), ["a/1", "b/1", "c", "a/2", "b/2"]);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::args_parser::AppArgs;
+use crate::context::AppContext;
+use crate::engines::fuzz;
+use crate::engines::queue::TaskQueue;
+use crate::engines::{build_request, get_http_client};
+use log::{debug, info, warn};
+use reqwest::Method;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+// 目标不可用期间，检查的间隔秒数
+const DOWN_INTERVAL: u64 = 5;
+
+/**
+ * 定期请求一个已知正常的 URL（默认为 target 根目录），监控目标是否存活
+ * 请求失败或状态码发生变化时暂停扫描，并把故障期间失败的任务重新入队，恢复后继续扫描
+ */
+pub async fn canary(args: Arc<AppArgs>, app_context: Arc<AppContext>, task_queue: Arc<TaskQueue>) {
+    if args.canary_interval == 0 {
+        return;
+    }
+    // fuzz 模式下请求中的标记替换为一个固定的随机值
+    let value = fuzz::random_value(&args);
+    let url = match &args.canary_url {
+        Some(v) => v.to_owned(),
+        None if args.fuzz.url => fuzz::request_url(&args, &value),
+        None => args.target.clone(),
+    };
+    let url = url.as_str();
+
+    // 扫描开始时的状态码作为正常状态
+    let expected = check(&args, &app_context, url, &value).await;
+    debug!("canary {} expected status: {:?}", url, expected);
+    if expected.is_none() {
+        warn!("canary 请求 {} 失败，不启用目标存活监控", url);
+        return;
+    }
+
+    loop {
+        let interval = if app_context.canary_down.load(Ordering::SeqCst) {
+            DOWN_INTERVAL
+        } else {
+            args.canary_interval
+        };
+        // 扫描结束后立即退出，不等待下一次检查
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+            _ = task_queue.closed() => break,
+        }
+
+        let status = check(&args, &app_context, url, &value).await;
+        // 持有 failures 锁时修改 canary_down，worker 记录失败的任务时看到的状态保持一致
+        let mut failures = app_context.failures.lock().unwrap();
+        if status == expected {
+            if app_context.canary_down.swap(false, Ordering::SeqCst) {
+                info!("目标已恢复，继续扫描");
+            }
+            // 目标正常期间失败的任务不需要重新入队
+            failures.recent.clear();
+        } else if !app_context.canary_down.load(Ordering::SeqCst) {
+            warn!(
+                "目标状态异常，canary: {}, 期望: {:?}, 实际: {:?}，暂停扫描",
+                url, expected, status
+            );
+            app_context.canary_down.store(true, Ordering::SeqCst);
+
+            // 上一次检查之后失败的任务可能是故障导致的，重新入队
+            let recent = std::mem::take(&mut failures.recent);
+            if !recent.is_empty() {
+                info!("{} 个失败的任务重新入队", recent.len());
+                task_queue.requeue(recent);
+            }
+        }
+    }
+    debug!("canary stop.");
+}
+
+async fn check(args: &AppArgs, app_context: &AppContext, url: &str, value: &str) -> Option<u16> {
+    let http_client = get_http_client(app_context, 0);
+    match build_request(&http_client, args, Method::GET, url, value)
+        .send()
+        .await
+    {
+        Ok(r) => Some(r.status().as_u16()),
+        Err(e) => {
+            debug!("canary request failed, url: {}, error: {}", url, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use itertools::Itertools;
+use log::{error, warn};
+use std::process::exit;
+use tokio::fs::read_to_string;
+
+/**
+ * 多个字典的组合方式
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CombineMode {
+    // 笛卡尔积，每个字典中的每一项都与其他字典中的每一项组合
+    #[default]
+    ClusterBomb,
+    // 按行号一一对应组合，以最短的字典为准
+    Pitchfork,
+}
+
+impl CombineMode {
+    pub fn parse(raw: &str) -> Result<Self, &'static str> {
+        match raw.to_lowercase().as_str() {
+            "cluster-bomb" | "clusterbomb" => Ok(CombineMode::ClusterBomb),
+            "pitchfork" => Ok(CombineMode::Pitchfork),
+            _ => Err("combine 只能为 cluster-bomb 或 pitchfork！"),
+        }
+    }
+}
+
+/**
+ * 模板中的一段，固定的文本或者某个字典的占位符
+ */
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Slot(usize),
+}
+
+/**
+ * 通过模板组合多个字典，例如 {DIRS}/{FILES}%EXT%
+ * 同一个字典在模板中出现多次时使用相同的值，模板和字典中的 %EXT% 等占位符之后按字典模式展开
+ */
+#[derive(Debug, Clone)]
+pub struct Combination {
+    parts: Vec<Part>,
+    // 字典名称和文件路径
+    wordlists: Vec<(String, String)>,
+    mode: CombineMode,
+}
+
+impl Combination {
+    /**
+     * 解析模板和 NAME=FILE 格式的字典参数，模板中的字典和指定的字典需要一一对应
+     */
+    pub fn parse(
+        template: &str,
+        wordlists: &[String],
+        mode: CombineMode,
+    ) -> Result<Self, &'static str> {
+        let mut named: Vec<(String, String)> = vec![];
+        for raw in wordlists {
+            let (name, path) = raw.split_once('=').ok_or("字典的格式应为 NAME=FILE！")?;
+            if !is_name(name) || path.is_empty() {
+                return Err("字典的格式应为 NAME=FILE，NAME 只能包含字母、数字和下划线！");
+            }
+            if named.iter().any(|it| it.0 == name) {
+                return Err("字典名称不能重复！");
+            }
+            named.push((name.to_owned(), path.to_owned()));
+        }
+
+        let mut parts: Vec<Part> = vec![];
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let name = rest[start + 1..]
+                .find('}')
+                .map(|end| &rest[start + 1..start + 1 + end])
+                .filter(|it| is_name(it));
+            // 不是 {NAME} 格式的花括号按普通文本处理
+            let Some(name) = name else {
+                text.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+                continue;
+            };
+            let index = named
+                .iter()
+                .position(|it| it.0 == name)
+                .ok_or("模板中使用了未指定的字典！")?;
+            text.push_str(&rest[..start]);
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(Part::Slot(index));
+            rest = &rest[start + name.len() + 2..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        for index in 0..named.len() {
+            if !parts
+                .iter()
+                .any(|it| matches!(it, Part::Slot(i) if *i == index))
+            {
+                return Err("指定的字典没有在模板中使用！");
+            }
+        }
+        if named.is_empty() {
+            return Err("模板中至少需要使用一个字典！");
+        }
+
+        Ok(Self {
+            parts,
+            wordlists: named,
+            mode,
+        })
+    }
+
+    /**
+     * 读取全部字典，返回按组合方式逐个生成的迭代器，组合结果不会预先展开
+     */
+    pub async fn load(&self) -> Combinations {
+        let mut lists = vec![];
+        for (name, path) in &self.wordlists {
+            // 缺少任何一个字典都无法组合，直接退出
+            let content = match read_to_string(path).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("读取字典 {} 的文件 {} 出错，错误：{:?}", name, path, e);
+                    exit(-1);
+                }
+            };
+            let entries = content
+                .lines()
+                .map(|it| it.trim())
+                .filter(|it| !it.is_empty() && !it.starts_with('#'))
+                .map(|it| it.to_owned())
+                .collect::<Vec<String>>();
+            if entries.is_empty() {
+                warn!("字典 {} 中没有可用的内容", name);
+            }
+            lists.push(entries);
+        }
+
+        if self.mode == CombineMode::Pitchfork
+            && lists.iter().map(|it| it.len()).unique().count() > 1
+        {
+            warn!("pitchfork 模式下各个字典的行数不一致，以最短的字典为准");
+        }
+        self.combine(lists)
+    }
+
+    /**
+     * 按模板组合已经读取的字典，lists 的顺序与指定字典的顺序一致
+     */
+    fn combine(&self, lists: Vec<Vec<String>>) -> Combinations {
+        let done = lists.iter().any(|it| it.is_empty());
+        Combinations {
+            parts: self.parts.clone(),
+            indices: vec![0; lists.len()],
+            lists,
+            mode: self.mode,
+            done,
+        }
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/**
+ * 按组合方式逐个生成模板渲染后的结果
+ * cluster-bomb 模式下第一个字典变化最慢，最后一个字典变化最快
+ */
+#[derive(Debug)]
+pub struct Combinations {
+    parts: Vec<Part>,
+    lists: Vec<Vec<String>>,
+    mode: CombineMode,
+    indices: Vec<usize>,
+    done: bool,
+}
+
+impl Combinations {
+    /**
+     * 计算组合后的总任务数，weight 为一行展开后的任务数
+     * 同一个字典在模板中出现多次时，每次出现都会按占位符展开
+     */
+    pub fn total(&self, weight: impl Fn(&str) -> u64) -> u64 {
+        let text = self
+            .parts
+            .iter()
+            .filter_map(|it| match it {
+                Part::Text(t) => Some(t.as_str()),
+                Part::Slot(_) => None,
+            })
+            .collect::<String>();
+        let template_weight = weight(&text);
+        let occurrences = |index: usize| {
+            self.parts
+                .iter()
+                .filter(|it| matches!(it, Part::Slot(i) if *i == index))
+                .count() as u32
+        };
+
+        match self.mode {
+            CombineMode::ClusterBomb => {
+                self.lists
+                    .iter()
+                    .enumerate()
+                    .fold(template_weight, |total, (index, list)| {
+                        let times = occurrences(index);
+                        let sum = list.iter().fold(0u64, |sum, entry| {
+                            sum.saturating_add(weight(entry).saturating_pow(times))
+                        });
+                        total.saturating_mul(sum)
+                    })
+            }
+            CombineMode::Pitchfork => {
+                let rows = self.lists.iter().map(|it| it.len()).min().unwrap_or(0);
+                (0..rows).fold(0u64, |total, row| {
+                    let row_weight =
+                        self.lists
+                            .iter()
+                            .enumerate()
+                            .fold(template_weight, |w, (index, list)| {
+                                w.saturating_mul(
+                                    weight(&list[row]).saturating_pow(occurrences(index)),
+                                )
+                            });
+                    total.saturating_add(row_weight)
+                })
+            }
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(t) => line.push_str(t),
+                Part::Slot(index) => line.push_str(&self.lists[*index][self.indices[*index]]),
+            }
+        }
+        line
+    }
+
+    /**
+     * 移动到下一个组合，没有更多组合时返回 false
+     */
+    fn advance(&mut self) -> bool {
+        match self.mode {
+            CombineMode::ClusterBomb => {
+                for index in (0..self.indices.len()).rev() {
+                    self.indices[index] += 1;
+                    if self.indices[index] < self.lists[index].len() {
+                        return true;
+                    }
+                    self.indices[index] = 0;
+                }
+                false
+            }
+            CombineMode::Pitchfork => {
+                for index in self.indices.iter_mut() {
+                    *index += 1;
+                }
+                self.indices
+                    .iter()
+                    .zip(&self.lists)
+                    .all(|(index, list)| *index < list.len())
+            }
+        }
+    }
+}
+
+impl Iterator for Combinations {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let line = self.render();
+        self.done = !self.advance();
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combine(template: &str, lists: &[&[&str]], mode: CombineMode) -> Combinations {
+        let names = ["A", "B", "C"];
+        let wordlists: Vec<String> = (0..lists.len())
+            .map(|i| format!("{}={}.txt", names[i], names[i]))
+            .collect();
+        let lists = lists
+            .iter()
+            .map(|list| list.iter().map(|it| it.to_string()).collect())
+            .collect();
+        Combination::parse(template, &wordlists, mode)
+            .unwrap()
+            .combine(lists)
+    }
+
+    // 每个 %EXT% 展开为两个任务
+    fn weight(line: &str) -> u64 {
+        2u64.pow(line.matches("%EXT%").count() as u32)
+    }
+
+    #[test]
+    fn cluster_bomb_expansion() {
+        let result: Vec<String> = combine(
+            "{A}/{B}",
+            &[&["x", "y"], &["1", "2", "3"]],
+            CombineMode::ClusterBomb,
+        )
+        .collect();
+        assert_eq!(result, ["x/1", "x/2", "x/3", "y/1", "y/2", "y/3"]);
+    }
+
+    #[test]
+    fn pitchfork_expansion() {
+        let combinations = combine(
+            "{A}/{B}",
+            &[&["x", "y", "z"], &["1", "2"]],
+            CombineMode::Pitchfork,
+        );
+        assert_eq!(combinations.total(|_| 1), 2);
+        assert_eq!(combinations.collect::<Vec<_>>(), ["x/1", "y/2"]);
+    }
+
+    #[test]
+    fn repeated_slot_uses_same_value() {
+        let result: Vec<String> = combine(
+            "{A}-{B}-{A}",
+            &[&["x", "y"], &["1"]],
+            CombineMode::ClusterBomb,
+        )
+        .collect();
+        assert_eq!(result, ["x-1-x", "y-1-y"]);
+    }
+
+    #[test]
+    fn empty_wordlist_yields_nothing() {
+        for mode in [CombineMode::ClusterBomb, CombineMode::Pitchfork] {
+            let combinations = combine("{A}/{B}", &[&["x"], &[]], mode);
+            assert_eq!(combinations.total(weight), 0);
+            assert_eq!(combinations.count(), 0);
+        }
+    }
+
+    #[test]
+    fn total_matches_expansion() {
+        let cases: [(&str, &[&[&str]], CombineMode, u64); 4] = [
+            (
+                "{A}%EXT%/{B}",
+                &[&["a", "b%EXT%"], &["c", "d"]],
+                CombineMode::ClusterBomb,
+                12,
+            ),
+            (
+                "{A}%EXT%/{B}",
+                &[&["a", "b%EXT%"], &["c", "d"]],
+                CombineMode::Pitchfork,
+                6,
+            ),
+            ("{A}{A}", &[&["a%EXT%", "b"]], CombineMode::ClusterBomb, 5),
+            ("{A}{A}", &[&["a%EXT%", "b"]], CombineMode::Pitchfork, 5),
+        ];
+        for (template, lists, mode, expected) in cases {
+            let combinations = combine(template, lists, mode);
+            assert_eq!(combinations.total(weight), expected, "{}", template);
+            let expanded: u64 = combinations.map(|line| weight(&line)).sum();
+            assert_eq!(expanded, expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn parse_template() {
+        let wordlists = ["A=a.txt".to_owned()];
+        let combination = Combination::parse("{a-b}{A}{", &wordlists, CombineMode::ClusterBomb);
+        let result: Vec<String> = combination
+            .unwrap()
+            .combine(vec![vec!["x".to_owned()]])
+            .collect();
+        assert_eq!(result, ["{a-b}x{"]);
+
+        let errors = [
+            ("{B}", vec!["A=a.txt"]),
+            ("{A}", vec!["A=a.txt", "B=b.txt"]),
+            ("{A}", vec!["A"]),
+            ("{A}", vec!["A-1=a.txt"]),
+            ("{A}", vec!["A=a.txt", "A=b.txt"]),
+            ("admin", vec![]),
+        ];
+        for (template, wordlists) in errors {
+            let wordlists: Vec<String> = wordlists.iter().map(|it| it.to_string()).collect();
+            assert!(
+                Combination::parse(template, &wordlists, CombineMode::ClusterBomb).is_err(),
+                "{} {:?}",
+                template,
+                wordlists
+            );
+        }
+    }
+}
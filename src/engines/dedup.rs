@@ -0,0 +1,241 @@
+use crate::context::EnumResult;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// simhash 海明距离不超过该值时认为两个页面相似
+pub const SIMHASH_DISTANCE: u32 = 3;
+
+/**
+ * 计算响应体的精确 hash 和 simhash 指纹
+ * 计算 simhash 前会先做归一化：转小写、去掉页面中回显的请求路径、连续的数字替换为 0
+ */
+pub fn fingerprint(content: &str, url: &str) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    let body_hash = hasher.finish();
+
+    (body_hash, simhash(&normalize(content, url)))
+}
+
+fn normalize(content: &str, url: &str) -> String {
+    let mut text = content.to_lowercase();
+
+    // 很多自定义的 404 页面会把请求的路径回显出来
+    if let Ok(u) = reqwest::Url::parse(url) {
+        let path = u.path().to_lowercase();
+        if path.len() > 1 {
+            text = text.replace(&path, " ");
+            text = text.replace(path.trim_start_matches('/'), " ");
+        }
+    }
+
+    // 连续的数字统一替换为一个 0，避免时间戳、请求 ID 等影响指纹
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            if !result.ends_with('0') {
+                result.push('0');
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn simhash(text: &str) -> u64 {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|it| !it.is_empty())
+        .collect::<Vec<&str>>();
+
+    // 使用 3 个单词组成的 shingle 作为特征，单词太少时直接使用单词
+    let features = if words.len() < 3 {
+        words.iter().map(|it| vec![*it]).collect::<Vec<Vec<&str>>>()
+    } else {
+        words.windows(3).map(|it| it.to_vec()).collect()
+    };
+
+    let mut weights = [0i64; 64];
+    for feature in features {
+        let mut hasher = DefaultHasher::new();
+        feature.hash(&mut hasher);
+        let h = hasher.finish();
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if h & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0)
+        .fold(0u64, |acc, (bit, _)| acc | (1 << bit))
+}
+
+#[derive(Debug)]
+struct Cluster {
+    status_code: u16,
+    simhash: u64,
+    sample_url: String,
+    count: u64,
+}
+
+/**
+ * 单个结果的聚类情况
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    // 没有响应体指纹或者关闭了折叠，不参与聚类
+    Unique,
+    // 属于某个聚类，且该聚类的数量还没有超过阈值
+    Member(usize),
+    // 所属的聚类已经超过阈值，不再单独记录
+    Collapsed,
+}
+
+/**
+ * 将相同或相似的响应聚类，某个聚类的结果数量超过阈值后，整个聚类在输出中只保留一条汇总记录
+ */
+#[derive(Debug)]
+pub struct Deduplicator {
+    threshold: u64,
+    clusters: Vec<Cluster>,
+    // (status_code, body_hash) -> 聚类下标
+    exact: HashMap<(u16, u64), usize>,
+}
+
+impl Deduplicator {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            clusters: vec![],
+            exact: HashMap::new(),
+        }
+    }
+
+    /**
+     * 将结果加入所属的聚类，返回该结果的聚类情况
+     */
+    pub fn check(&mut self, result: &EnumResult) -> Verdict {
+        let (body_hash, simhash) = match (result.body_hash, result.simhash) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Verdict::Unique,
+        };
+        if self.threshold == 0 {
+            return Verdict::Unique;
+        }
+
+        let idx = match self.exact.get(&(result.status_code, body_hash)) {
+            Some(idx) => *idx,
+            None => {
+                let idx = self
+                    .clusters
+                    .iter()
+                    .position(|it| {
+                        it.status_code == result.status_code
+                            && (it.simhash ^ simhash).count_ones() <= SIMHASH_DISTANCE
+                    })
+                    .unwrap_or_else(|| {
+                        self.clusters.push(Cluster {
+                            status_code: result.status_code,
+                            simhash,
+                            sample_url: result.url.clone(),
+                            count: 0,
+                        });
+                        self.clusters.len() - 1
+                    });
+                self.exact.insert((result.status_code, body_hash), idx);
+                idx
+            }
+        };
+
+        let cluster = &mut self.clusters[idx];
+        cluster.count += 1;
+        if cluster.count <= self.threshold {
+            Verdict::Member(idx)
+        } else {
+            Verdict::Collapsed
+        }
+    }
+
+    /**
+     * 聚类被折叠时返回其汇总记录，以第一个结果作为样例，未超过阈值时返回 None
+     */
+    pub fn summary(&self, idx: usize) -> Option<String> {
+        let cluster = &self.clusters[idx];
+        if cluster.count <= self.threshold {
+            return None;
+        }
+        Some(format!(
+            "{} {} (+{} similar responses collapsed)",
+            cluster.status_code,
+            cluster.sample_url,
+            cluster.count - 1
+        ))
+    }
+
+    /**
+     * 是否有聚类超过了阈值
+     */
+    pub fn collapsed(&self) -> bool {
+        self.clusters.iter().any(|it| it.count > self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(path: &str, content: &str) -> EnumResult {
+        let url = format!("http://example.com/{}", path);
+        let (body_hash, simhash) = fingerprint(content, &url);
+        EnumResult {
+            status_code: 200,
+            url,
+            body_hash: Some(body_hash),
+            simhash: Some(simhash),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn collapse_cluster_over_threshold() {
+        let mut deduplicator = Deduplicator::new(2);
+        let page = "custom error page, the page you requested does not exist";
+        let verdicts = (0..4)
+            .map(|i| deduplicator.check(&result(&format!("a{}", i), page)))
+            .collect::<Vec<Verdict>>();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Member(0),
+                Verdict::Member(0),
+                Verdict::Collapsed,
+                Verdict::Collapsed
+            ]
+        );
+
+        let unique = deduplicator.check(&result("b", "welcome to the admin console of example"));
+        assert_eq!(unique, Verdict::Member(1));
+        assert!(deduplicator.collapsed());
+        assert_eq!(
+            deduplicator.summary(0).as_deref(),
+            Some("200 http://example.com/a0 (+3 similar responses collapsed)")
+        );
+        assert_eq!(deduplicator.summary(1), None);
+    }
+
+    #[test]
+    fn no_fingerprint_or_threshold() {
+        let mut deduplicator = Deduplicator::new(0);
+        assert_eq!(deduplicator.check(&result("a", "x")), Verdict::Unique);
+        let mut deduplicator = Deduplicator::new(1);
+        assert_eq!(deduplicator.check(&EnumResult::default()), Verdict::Unique);
+    }
+}
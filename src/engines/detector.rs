@@ -0,0 +1,132 @@
+use regex::Regex;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Severity::Low => "LOW",
+            Severity::Medium => "MEDIUM",
+            Severity::High => "HIGH",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub name: &'static str,
+    pub severity: Severity,
+}
+
+/**
+ * 敏感内容检测，在命中结果的响应体中识别密钥、数据库连接串、phpinfo、报错堆栈、SQL 导出等内容
+ */
+#[derive(Debug)]
+pub struct Detector {
+    rules: Vec<(&'static str, Severity, Regex)>,
+}
+
+impl Detector {
+    pub fn new() -> Self {
+        let rules = [
+            (
+                "private key",
+                Severity::High,
+                r"-----BEGIN (?:RSA |EC |DSA |OPENSSH |PGP |ENCRYPTED )?PRIVATE KEY",
+            ),
+            (
+                "aws access key",
+                Severity::High,
+                r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+            ),
+            (
+                "aws secret key",
+                Severity::High,
+                r"(?i)aws_secret_access_key\s*[=:]\s*\S{20,}",
+            ),
+            (
+                "aliyun access key",
+                Severity::High,
+                r"\bLTAI[0-9A-Za-z]{12,20}\b",
+            ),
+            (
+                "google api key",
+                Severity::Medium,
+                r"\bAIza[0-9A-Za-z_\-]{35}\b",
+            ),
+            (
+                "database dsn",
+                Severity::High,
+                r"(?i)\b(?:mysql|postgres(?:ql)?|mongodb(?:\+srv)?|redis|amqp|mssql|sqlserver)://[^\s:@/'\x22]*:[^\s@'\x22]+@",
+            ),
+            (
+                "connection string",
+                Severity::High,
+                r"(?i)(?:server|data source)\s*=[^;\x22]+;[^\x22]*(?:password|pwd)\s*=",
+            ),
+            (
+                "jdbc url",
+                Severity::Medium,
+                r"(?i)\bjdbc:[a-z0-9]+:[^\s'\x22<]+",
+            ),
+            (
+                "password",
+                Severity::Medium,
+                r"(?im)^\s*[\x22']?[A-Z0-9_]*(?:PASSWORD|PASSWD|SECRET)[A-Z0-9_]*[\x22']?\s*[=:]\s*[\x22']?[^\s\x22']{3,}",
+            ),
+            (
+                "phpinfo",
+                Severity::Medium,
+                r"(?i)<title>(?:PHP [\d.]+ - )?phpinfo\(\)</title>",
+            ),
+            (
+                "sql dump",
+                Severity::High,
+                r"(?i)-- (?:MySQL|PostgreSQL) (?:database )?dump|(?s)CREATE TABLE .*?INSERT INTO",
+            ),
+            (
+                "stack trace",
+                Severity::Low,
+                r"Traceback \(most recent call last\)|\bat [\w$.]+\([\w$]+\.java:\d+\)|(?:Fatal error|Warning|Parse error)</b>: .* on line <b>\d+|Server Error in '[^']*' Application|Stack trace:\s*#0",
+            ),
+        ];
+
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(name, severity, re)| (name, severity, Regex::new(re).unwrap()))
+                .collect(),
+        }
+    }
+
+    pub fn detect(&self, content: &str) -> Vec<Finding> {
+        self.rules
+            .iter()
+            .filter(|(_, _, re)| re.is_match(content))
+            .map(|(name, severity, _)| Finding {
+                name,
+                severity: *severity,
+            })
+            .collect()
+    }
+
+    /**
+     * 根据名称查找检测规则，用于还原从远程 worker 收到的检测结果
+     */
+    pub fn find(&self, name: &str) -> Option<Finding> {
+        self.rules
+            .iter()
+            .find(|(rule_name, _, _)| *rule_name == name)
+            .map(|(name, severity, _)| Finding {
+                name,
+                severity: *severity,
+            })
+    }
+}
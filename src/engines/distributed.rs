@@ -0,0 +1,673 @@
+use crate::args_parser::AppArgs;
+use crate::context::{AppContext, EnumResult, Progress, RedirectHop};
+use crate::engines::builder::enum_keyspace;
+use crate::engines::detector::Detector;
+use crate::engines::keyspace::{KeyRange, Keyspace};
+use crate::engines::queue::{Task, TaskQueue};
+use crate::engines::waf;
+use async_channel::{Receiver, Sender};
+use log::{error, info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+// 远程 worker 同时持有的任务数量为并发数的 CREDIT_FACTOR 倍
+const CREDIT_FACTOR: usize = 2;
+// 分发给远程 worker 的 keyspace 区间最多包含的路径数量，额度按路径计算
+const REMOTE_PART_SIZE: u64 = 16;
+
+/**
+ * 进程在分布式模式中的角色
+ * 协调者只生成任务和保存结果，远程 worker 从协调者获取任务、发送请求并返回结果
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Role {
+    #[default]
+    Local,
+    // 监听的地址
+    Coordinator(String),
+    // 协调者的地址
+    Worker(String),
+}
+
+/*
+ * 协调者与远程 worker 之间的协议，每行一个 JSON 消息，type 字段为消息类型：
+ * worker -> 协调者
+ *   hello    {token, target, suffixes}  握手，token、target 和后缀需要与协调者一致
+ *   pull     {count}             申请 count 个路径的额度
+ *   ack      {id}                编号为 id 的任务处理完成，同时归还任务包含的路径数量的额度
+ *   result   {result}            扫描结果
+ *   failed   {task, error}       重试全部失败的任务
+ *   bye                          所有任务处理完成，断开连接
+ * 协调者 -> worker
+ *   welcome {seed} / reject {reason}  握手结果，seed 为随机顺序的种子
+ *   task     {id, path | range}  单个路径，或者 [length, start, end] 表示的 keyspace 区间
+ *                                队列中的区间按额度切分成最多 REMOTE_PART_SIZE 个路径的分段，id 为分段的编号
+ *   end                          任务队列已关闭，不会再有新任务
+ */
+
+/**
+ * 协调者：监听 TCP 端口，把任务队列中的任务分发给连接上来的远程 worker，收到的结果交给 saver
+ * 任务队列关闭后不再接受新的连接，所有连接断开后退出
+ */
+pub async fn coordinator(
+    listen: String,
+    args: Arc<AppArgs>,
+    app_context: Arc<AppContext>,
+    task_queue: Arc<TaskQueue>,
+    result_channel: Sender<Arc<EnumResult>>,
+    progress: Arc<Progress>,
+) {
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("监听 {} 失败，error: {}", listen, e);
+            exit(-1);
+        }
+    };
+    info!("协调者已启动，监听 {}，等待远程 worker 连接...", listen);
+
+    let mut handlers = vec![];
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    handlers.push(tokio::spawn(serve(
+                        stream,
+                        peer.to_string(),
+                        Arc::clone(&args),
+                        Arc::clone(&app_context),
+                        Arc::clone(&task_queue),
+                        result_channel.clone(),
+                        Arc::clone(&progress),
+                    )));
+                }
+                Err(e) => warn!("接受连接失败，error: {}", e),
+            },
+            _ = task_queue.closed() => break,
+        }
+    }
+    drop(listener);
+
+    for h in handlers {
+        let _ = h.await;
+    }
+    info!("coordinator stop.");
+}
+
+/**
+ * 处理一个远程 worker 的连接
+ * 已经分发但还没有确认的分段记录在 outstanding 中，连接意外断开时和未分发完的区间一起重新放入队列
+ */
+async fn serve(
+    stream: TcpStream,
+    peer: String,
+    args: Arc<AppArgs>,
+    app_context: Arc<AppContext>,
+    task_queue: Arc<TaskQueue>,
+    result_channel: Sender<Arc<EnumResult>>,
+    progress: Arc<Progress>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // 握手，target 和后缀不一致时生成的任务无法对应
+    let hello = match read_message(&mut lines).await {
+        Some(v) if v["type"] == "hello" => v,
+        _ => {
+            warn!("远程 worker {} 握手失败", peer);
+            return;
+        }
+    };
+    let token = args.token.as_deref().unwrap_or_default();
+    if !token_matches(hello["token"].as_str().unwrap_or_default(), token) {
+        warn!("远程 worker {} 的 token 不一致，拒绝连接", peer);
+        let reject = json!({"type": "reject", "reason": "token 不一致"});
+        let _ = write_message(&mut writer, &reject).await;
+        return;
+    }
+    let keyspace = enum_keyspace(&args);
+    if hello["target"] != json!(args.target) || hello["suffixes"] != json!(keyspace.suffixes()) {
+        warn!(
+            "远程 worker {} 的 target 或后缀与协调者不一致，拒绝连接",
+            peer
+        );
+        let reject = json!({"type": "reject", "reason": "target 或后缀与协调者不一致"});
+        let _ = write_message(&mut writer, &reject).await;
+        return;
+    }
+    let welcome = json!({"type": "welcome", "seed": keyspace.seed()});
+    if write_message(&mut writer, &welcome).await.is_err() {
+        return;
+    }
+    info!("远程 worker {} 已连接", peer);
+    progress.running_workers.fetch_add(1, Ordering::Relaxed);
+
+    let detector = Detector::new();
+    let mut credits: u64 = 0;
+    // 正在切分的队列任务
+    let mut splitting: Option<(u64, Task)> = None;
+    let mut next_part: u64 = 0;
+    // 分段编号 -> (队列任务编号, 分段)
+    let mut outstanding: HashMap<u64, (u64, Task)> = HashMap::new();
+    // 队列任务已分发但还没有确认的分段数量
+    let mut parts_left: HashMap<u64, u64> = HashMap::new();
+    let mut ended = false;
+    let mut finished = false;
+    'serve: loop {
+        while credits > 0 {
+            let (queue_id, task) = match splitting.take() {
+                Some(v) => v,
+                None => break,
+            };
+            let (part, rest) = split_task(task, credits.min(REMOTE_PART_SIZE));
+            splitting = rest.map(|rest| (queue_id, rest));
+            credits -= task_size(&part);
+            next_part += 1;
+            *parts_left.entry(queue_id).or_default() += 1;
+            let sent = write_message(&mut writer, &task_to_json(next_part, &part)).await;
+            outstanding.insert(next_part, (queue_id, part));
+            if sent.is_err() {
+                break 'serve;
+            }
+        }
+
+        tokio::select! {
+            line = lines.next_line() => {
+                let message = match line {
+                    Ok(Some(v)) => serde_json::from_str::<Value>(&v).unwrap_or_default(),
+                    _ => break,
+                };
+                match message["type"].as_str() {
+                    Some("pull") => credits += message["count"].as_u64().unwrap_or(0),
+                    Some("ack") => {
+                        let id = message["id"].as_u64().unwrap_or_default();
+                        if let Some((queue_id, part)) = outstanding.remove(&id) {
+                            let size = task_size(&part);
+                            progress.done.fetch_add(size, Ordering::Relaxed);
+                            credits += size;
+                            let left = parts_left.entry(queue_id).or_default();
+                            *left -= 1;
+                            let splitted = matches!(splitting, Some((v, _)) if v == queue_id);
+                            if *left == 0 && !splitted {
+                                parts_left.remove(&queue_id);
+                                if task_queue.task_done(queue_id) {
+                                    task_queue.finish(&app_context);
+                                }
+                            }
+                        }
+                    }
+                    Some("result") => {
+                        if let Some(result) = result_from_json(&message["result"], &detector) {
+                            let _ = result_channel.send(Arc::new(result)).await;
+                        }
+                    }
+                    Some("failed") => {
+                        if let Some(task) = message["task"].as_str() {
+                            let error = message["error"].as_str().unwrap_or_default();
+                            app_context
+                                .failures
+                                .lock()
+                                .unwrap()
+                                .failed
+                                .insert(task.to_owned(), error.to_owned());
+                        }
+                    }
+                    Some("bye") => {
+                        finished = true;
+                        break;
+                    }
+                    _ => warn!("远程 worker {} 发送了无法识别的消息：{}", peer, message),
+                }
+            }
+            task = task_queue.recv(), if credits > 0 && splitting.is_none() && !ended => match task {
+                Some(task) => splitting = Some(task),
+                None => {
+                    ended = true;
+                    let _ = write_message(&mut writer, &json!({"type": "end"})).await;
+                }
+            },
+            _ = task_queue.closed(), if !ended => {
+                ended = true;
+                let _ = write_message(&mut writer, &json!({"type": "end"})).await;
+            }
+        }
+    }
+
+    // 连接意外断开，已经分发但没有确认的任务重新放入队列
+    if !outstanding.is_empty() {
+        warn!(
+            "远程 worker {} 断开连接，{} 个未完成的任务重新入队",
+            peer,
+            outstanding.len()
+        );
+    }
+    for (_, (_, part)) in outstanding {
+        task_queue.resend(render_task(part));
+    }
+    if let Some((queue_id, rest)) = splitting {
+        task_queue.resend(render_task(rest));
+        parts_left.entry(queue_id).or_default();
+    }
+    for queue_id in parts_left.into_keys() {
+        if task_queue.task_done(queue_id) {
+            task_queue.finish(&app_context);
+        }
+    }
+    progress.running_workers.fetch_sub(1, Ordering::Relaxed);
+    if finished {
+        info!("远程 worker {} 已完成", peer);
+    }
+}
+
+/**
+ * 远程 worker：连接到协调者，把收到的任务放入本地的任务队列，由本地的 worker 处理，
+ * 处理完成的任务编号和扫描结果发送回协调者
+ */
+pub async fn remote(
+    coordinator: String,
+    args: Arc<AppArgs>,
+    app_context: Arc<AppContext>,
+    task_queue: Arc<TaskQueue>,
+    acks: Receiver<u64>,
+    result_channel: Receiver<Arc<EnumResult>>,
+) {
+    let stream = match TcpStream::connect(&coordinator).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("连接协调者 {} 失败，error: {}", coordinator, e);
+            exit(-1);
+        }
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let keyspace = enum_keyspace(&args);
+    let hello = json!({
+        "type": "hello",
+        "token": args.token,
+        "target": args.target,
+        "suffixes": keyspace.suffixes(),
+    });
+    let welcome = match write_message(&mut writer, &hello).await {
+        Ok(_) => read_message(&mut lines).await.unwrap_or_default(),
+        Err(_) => Value::Null,
+    };
+    if welcome["type"] != "welcome" {
+        error!(
+            "协调者 {} 拒绝连接：{}",
+            coordinator,
+            welcome["reason"].as_str().unwrap_or("握手失败")
+        );
+        exit(-1);
+    }
+    info!("已连接到协调者 {}", coordinator);
+    // 随机顺序时使用协调者的种子渲染 keyspace 区间
+    let keyspace = Arc::new(keyspace.with_seed(welcome["seed"].as_u64()));
+
+    // 本地任务编号到协调者任务编号的映射
+    let ids: Arc<Mutex<HashMap<u64, u64>>> = Arc::new(Mutex::new(HashMap::new()));
+    let feeder = tokio::spawn(feed(
+        lines,
+        keyspace,
+        Arc::clone(&app_context),
+        Arc::clone(&task_queue),
+        Arc::clone(&ids),
+    ));
+
+    let count = (args.task_count * CREDIT_FACTOR) as u64 * REMOTE_PART_SIZE;
+    let pull = json!({"type": "pull", "count": count});
+    let mut connected = write_message(&mut writer, &pull).await.is_ok();
+    // 本地的 worker 全部退出后结果通道关闭，此时所有任务都已经确认
+    while connected {
+        tokio::select! {
+            biased;
+            Ok(local_id) = acks.recv() => {
+                if let Some(id) = ids.lock().await.remove(&local_id) {
+                    connected = write_message(&mut writer, &json!({"type": "ack", "id": id})).await.is_ok();
+                }
+            }
+            result = result_channel.recv() => match result {
+                Ok(result) => {
+                    let message = json!({"type": "result", "result": result_to_json(&result)});
+                    connected = write_message(&mut writer, &message).await.is_ok();
+                }
+                Err(_) => break,
+            },
+        }
+    }
+    while let Ok(local_id) = acks.try_recv() {
+        if let Some(id) = ids.lock().await.remove(&local_id) {
+            let _ = write_message(&mut writer, &json!({"type": "ack", "id": id})).await;
+        }
+    }
+
+    // 重试全部失败的任务交给协调者写入 errors 文件
+    let failed = app_context.failures.lock().unwrap().failed.clone();
+    for (task, error) in failed {
+        let message = json!({"type": "failed", "task": task, "error": error});
+        let _ = write_message(&mut writer, &message).await;
+    }
+    let _ = write_message(&mut writer, &json!({"type": "bye"})).await;
+    let _ = writer.shutdown().await;
+    let _ = feeder.await;
+
+    // 请求统计只在远程 worker 本地输出
+    let summary = app_context.stats.lock().unwrap().summary();
+    for line in summary {
+        println!("{}", line);
+    }
+    info!("remote worker stop.");
+}
+
+/**
+ * 读取协调者分发的任务并放入本地的任务队列，收到 end 后按 builder 结束处理
+ * 与协调者的连接断开时终止扫描
+ */
+async fn feed(
+    mut lines: Lines<BufReader<OwnedReadHalf>>,
+    keyspace: Arc<Keyspace>,
+    app_context: Arc<AppContext>,
+    task_queue: Arc<TaskQueue>,
+    ids: Arc<Mutex<HashMap<u64, u64>>>,
+) {
+    loop {
+        let message = match read_message(&mut lines).await {
+            Some(v) => v,
+            None => {
+                error!("与协调者的连接已断开，终止扫描");
+                app_context.aborted.store(true, Ordering::SeqCst);
+                task_queue.close();
+                return;
+            }
+        };
+        match message["type"].as_str() {
+            Some("task") => {
+                let (id, task) = match task_from_json(&message, &keyspace) {
+                    Some(v) => v,
+                    None => {
+                        warn!("无法识别的任务：{}", message);
+                        continue;
+                    }
+                };
+                task_queue.expect(task_size(&task));
+                // 持有锁直到写入映射，避免任务在写入映射之前就被确认
+                let mut guard = ids.lock().await;
+                match task_queue.send(task).await {
+                    Some(local_id) => guard.insert(local_id, id),
+                    None => return,
+                };
+            }
+            Some("end") => {
+                info!("协调者的任务已全部分发");
+                if task_queue.builder_done() {
+                    task_queue.finish(&app_context);
+                }
+                return;
+            }
+            _ => warn!("协调者发送了无法识别的消息：{}", message),
+        }
+    }
+}
+
+async fn read_message(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Option<Value> {
+    let line = lines.next_line().await.ok()??;
+    serde_json::from_str(&line).ok()
+}
+
+async fn write_message(writer: &mut OwnedWriteHalf, message: &Value) -> std::io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+/**
+ * 任务包含的路径数量
+ */
+fn task_size(task: &Task) -> u64 {
+    match task {
+        Task::Path(_) => 1,
+        Task::Range(range) => range.end - range.start,
+    }
+}
+
+/**
+ * 从任务开头切分出最多 count 个路径，返回切分出的分段和剩余的部分
+ */
+fn split_task(task: Task, count: u64) -> (Task, Option<Task>) {
+    match task {
+        Task::Range(mut range) if range.end - range.start > count => {
+            let part = range.take(count);
+            (Task::Range(part), Some(Task::Range(range)))
+        }
+        task => (task, None),
+    }
+}
+
+/**
+ * 比较握手的 token，耗时与内容无关
+ */
+fn token_matches(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/**
+ * 把任务展开成路径，用于重新入队
+ */
+fn render_task(task: Task) -> Vec<String> {
+    match task {
+        Task::Path(path) => vec![path],
+        Task::Range(mut range) => {
+            let mut paths = vec![];
+            let mut buffer = String::new();
+            while range.next_into(&mut buffer) {
+                paths.push(buffer.clone());
+            }
+            paths
+        }
+    }
+}
+
+fn task_to_json(id: u64, task: &Task) -> Value {
+    match task {
+        Task::Path(path) => json!({"type": "task", "id": id, "path": path}),
+        Task::Range(range) => json!({
+            "type": "task",
+            "id": id,
+            "range": [range.length, range.start, range.end],
+        }),
+    }
+}
+
+fn task_from_json(message: &Value, keyspace: &Arc<Keyspace>) -> Option<(u64, Task)> {
+    let id = message["id"].as_u64()?;
+    if let Some(path) = message["path"].as_str() {
+        return Some((id, Task::Path(path.to_owned())));
+    }
+    let range = message["range"].as_array()?;
+    Some((
+        id,
+        Task::Range(KeyRange {
+            keyspace: Arc::clone(keyspace),
+            length: range.first()?.as_u64()? as usize,
+            start: range.get(1)?.as_u64()?,
+            end: range.get(2)?.as_u64()?,
+        }),
+    ))
+}
+
+fn result_to_json(result: &EnumResult) -> Value {
+    json!({
+        "status_code": result.status_code,
+        "status_line": result.status_line,
+        "headers": result
+            .headers
+            .iter()
+            .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+            .collect::<Vec<Value>>(),
+        "url": result.url,
+        "fuzz": result.fuzz,
+        "redirects": result
+            .redirects
+            .iter()
+            .map(|it| json!([it.status_code, it.location]))
+            .collect::<Vec<Value>>(),
+        "location": result.location,
+        "content": result.content,
+        "body_hash": result.body_hash,
+        "simhash": result.simhash,
+        "head_fallback": result.head_fallback,
+        "dir_listing": result.dir_listing,
+        "findings": result.findings.iter().map(|it| it.name).collect::<Vec<&str>>(),
+        "title": result.title,
+        "content_length": result.content_length,
+        "soft_404": result.soft_404,
+        "waf": result.waf,
+    })
+}
+
+/**
+ * 还原远程 worker 发送的扫描结果，无法解析的响应头、检测规则和 WAF 名称会被忽略
+ */
+fn result_from_json(value: &Value, detector: &Detector) -> Option<EnumResult> {
+    let mut headers = HeaderMap::new();
+    for header in value["headers"].as_array()? {
+        let name = header[0]
+            .as_str()
+            .and_then(|it| HeaderName::from_bytes(it.as_bytes()).ok());
+        let value = header[1]
+            .as_str()
+            .and_then(|it| HeaderValue::from_str(it).ok());
+        if let (Some(name), Some(value)) = (name, value) {
+            headers.append(name, value);
+        }
+    }
+    let get_string = |name: &str| value[name].as_str().map(|it| it.to_owned());
+
+    Some(EnumResult {
+        status_code: value["status_code"].as_u64()? as u16,
+        status_line: get_string("status_line").unwrap_or_default(),
+        headers,
+        url: get_string("url")?,
+        fuzz: get_string("fuzz"),
+        redirects: value["redirects"]
+            .as_array()?
+            .iter()
+            .filter_map(|it| {
+                Some(RedirectHop {
+                    status_code: it[0].as_u64()? as u16,
+                    location: it[1].as_str()?.to_owned(),
+                })
+            })
+            .collect(),
+        location: get_string("location"),
+        content: get_string("content"),
+        body_hash: value["body_hash"].as_u64(),
+        simhash: value["simhash"].as_u64(),
+        head_fallback: value["head_fallback"].as_bool().unwrap_or_default(),
+        dir_listing: value["dir_listing"].as_bool().unwrap_or_default(),
+        findings: value["findings"]
+            .as_array()?
+            .iter()
+            .filter_map(|it| detector.find(it.as_str()?))
+            .collect(),
+        title: get_string("title"),
+        content_length: value["content_length"].as_u64().unwrap_or_default(),
+        soft_404: value["soft_404"].as_bool().unwrap_or_default(),
+        waf: value["waf"].as_str().and_then(waf::find_signature),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn disconnect_requeues_without_recounting() {
+        let args = Arc::new(AppArgs {
+            target: "http://127.0.0.1/".to_owned(),
+            suffix: "php".to_owned(),
+            length: 1,
+            token: Some("secret".to_owned()),
+            ..Default::default()
+        });
+        let progress = Arc::new(Progress::default());
+        let task_queue = Arc::new(TaskQueue::new(16, Arc::clone(&progress)));
+        let keyspace = Arc::new(enum_keyspace(&args));
+        let size = keyspace.size(1);
+        task_queue.expect(size);
+        let range = KeyRange {
+            keyspace: Arc::clone(&keyspace),
+            length: 1,
+            start: 0,
+            end: size,
+        };
+        task_queue.send(Task::Range(range)).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (result_tx, _result_rx) = async_channel::unbounded();
+        let server = {
+            let args = Arc::clone(&args);
+            let task_queue = Arc::clone(&task_queue);
+            let progress = Arc::clone(&progress);
+            tokio::spawn(async move {
+                let (stream, peer) = listener.accept().await.unwrap();
+                let app_context = Arc::new(AppContext::new());
+                serve(
+                    stream,
+                    peer.to_string(),
+                    args,
+                    app_context,
+                    task_queue,
+                    result_tx,
+                    progress,
+                )
+                .await;
+            })
+        };
+
+        // 握手后领取一个分段，不确认就断开连接
+        let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let hello = json!({
+            "type": "hello",
+            "token": "secret",
+            "target": args.target,
+            "suffixes": keyspace.suffixes(),
+        });
+        write_message(&mut writer, &hello).await.unwrap();
+        assert_eq!(read_message(&mut lines).await.unwrap()["type"], "welcome");
+        write_message(&mut writer, &json!({"type": "pull", "count": 4}))
+            .await
+            .unwrap();
+        let task = read_message(&mut lines).await.unwrap();
+        assert_eq!(task["range"], json!([1, 0, 4]));
+        drop(writer);
+        drop(lines);
+        server.await.unwrap();
+
+        // 未确认的分段和剩余的区间重新入队，任务总数和已完成的数量不变
+        assert_eq!(progress.total.load(Ordering::Relaxed), size);
+        assert_eq!(progress.done.load(Ordering::Relaxed), 0);
+        task_queue.close();
+        let mut requeued = vec![];
+        while let Some((_, Task::Path(path))) = task_queue.recv().await {
+            requeued.push(path);
+        }
+        requeued.sort();
+        requeued.dedup();
+        assert_eq!(requeued.len() as u64, size);
+    }
+}
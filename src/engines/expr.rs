@@ -0,0 +1,522 @@
+use crate::context::EnumResult;
+use regex::Regex;
+
+/**
+ * 结果过滤表达式，例如：status in [200,403] && size > 120 && !body ~ "not found"
+ *
+ * 支持的字段：
+ *   status       状态码
+ *   size         响应体长度，HEAD 请求时取 Content-Length
+ *   url          完整的 URL
+ *   path         URL 中的路径部分
+ *   body         响应体
+ *   status_line  状态行，例如 HTTP/1.1 200 OK
+ *   header.<name> 指定响应头的值，例如 header.content-type
+ *   location     最终的跳转地址，没有跳转时为空
+ *   redirects    跟随的跳转次数
+ *   severity     检测到的敏感内容的最高等级，LOW、MEDIUM 或 HIGH，没有时为空
+ *   title        页面标题
+ *   fuzz         替换 FUZZ 标记的值，没有使用 FUZZ 标记时为空
+ *
+ * 支持的运算符：== != > >= < <= ~（正则匹配） !~（正则不匹配） in，以及 && || ! 和括号
+ */
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    Regex(Operand, Regex, bool),
+    In(Operand, Vec<Operand>),
+    Truthy(Operand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Field(Field),
+    Number(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Status,
+    Size,
+    Url,
+    Path,
+    Body,
+    StatusLine,
+    Header(String),
+    Location,
+    Redirects,
+    Severity,
+    Title,
+    Fuzz,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Str(String),
+}
+
+impl Value {
+    fn as_str(&self) -> String {
+        match self {
+            Value::Number(v) => v.to_string(),
+            Value::Str(v) => v.clone(),
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(v) => Some(*v),
+            Value::Str(v) => v.trim().parse::<f64>().ok(),
+        }
+    }
+}
+
+impl Field {
+    fn parse(name: &str) -> Result<Self, String> {
+        let field = match name {
+            "status" => Field::Status,
+            "size" => Field::Size,
+            "url" => Field::Url,
+            "path" => Field::Path,
+            "body" => Field::Body,
+            "status_line" => Field::StatusLine,
+            "location" => Field::Location,
+            "redirects" => Field::Redirects,
+            "severity" => Field::Severity,
+            "title" => Field::Title,
+            "fuzz" => Field::Fuzz,
+            _ => match name.strip_prefix("header.") {
+                Some(h) if !h.is_empty() => Field::Header(h.to_lowercase()),
+                _ => return Err(format!("未知的字段: {}", name)),
+            },
+        };
+        Ok(field)
+    }
+
+    fn value(&self, result: &EnumResult) -> Value {
+        match self {
+            Field::Status => Value::Number(result.status_code as f64),
+            Field::Size => Value::Number(result.content_length as f64),
+            Field::Url => Value::Str(result.url.clone()),
+            Field::Path => Value::Str(
+                reqwest::Url::parse(&result.url)
+                    .map(|it| it.path().to_owned())
+                    .unwrap_or_default(),
+            ),
+            Field::Body => Value::Str(result.content.clone().unwrap_or_default()),
+            Field::StatusLine => Value::Str(result.status_line.clone()),
+            Field::Header(name) => Value::Str(
+                result
+                    .headers
+                    .get(name.as_str())
+                    .and_then(|it| it.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned(),
+            ),
+            Field::Location => Value::Str(result.location.clone().unwrap_or_default()),
+            Field::Title => Value::Str(result.title.clone().unwrap_or_default()),
+            Field::Fuzz => Value::Str(result.fuzz.clone().unwrap_or_default()),
+            Field::Redirects => Value::Number(result.redirects.len() as f64),
+            Field::Severity => Value::Str(
+                result
+                    .findings
+                    .iter()
+                    .map(|it| it.severity)
+                    .max()
+                    .map(|it| it.to_string())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+impl Operand {
+    fn value(&self, result: &EnumResult) -> Value {
+        match self {
+            Operand::Field(f) => f.value(result),
+            Operand::Number(v) => Value::Number(*v),
+            Operand::Str(v) => Value::Str(v.clone()),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let tokens = tokenize(raw)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!(
+                "表达式在 {:?} 附近有多余内容",
+                parser.tokens[parser.pos]
+            ));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, result: &EnumResult) -> bool {
+        match self {
+            Expr::And(l, r) => l.eval(result) && r.eval(result),
+            Expr::Or(l, r) => l.eval(result) || r.eval(result),
+            Expr::Not(e) => !e.eval(result),
+            Expr::Compare(l, op, r) => compare(&l.value(result), *op, &r.value(result)),
+            Expr::Regex(o, re, negative) => re.is_match(&o.value(result).as_str()) != *negative,
+            Expr::In(o, list) => {
+                let v = o.value(result);
+                list.iter()
+                    .any(|it| compare(&v, CompareOp::Eq, &it.value(result)))
+            }
+            Expr::Truthy(o) => match o.value(result) {
+                Value::Number(v) => v != 0.0,
+                Value::Str(v) => !v.is_empty(),
+            },
+        }
+    }
+
+    /**
+     * 表达式中是否用到了响应体
+     */
+    pub fn need_body(&self) -> bool {
+        let is_body = |o: &Operand| matches!(o, Operand::Field(Field::Body));
+        match self {
+            Expr::And(l, r) | Expr::Or(l, r) => l.need_body() || r.need_body(),
+            Expr::Not(e) => e.need_body(),
+            Expr::Compare(l, _, r) => is_body(l) || is_body(r),
+            Expr::Regex(o, _, _) | Expr::Truthy(o) => is_body(o),
+            Expr::In(o, list) => is_body(o) || list.iter().any(is_body),
+        }
+    }
+}
+
+fn compare(l: &Value, op: CompareOp, r: &Value) -> bool {
+    // 两边都能转成数字时按数字比较，否则按字符串比较
+    let ordering = match (l.as_number(), r.as_number()) {
+        (Some(a), Some(b)) if matches!(l, Value::Number(_)) || matches!(r, Value::Number(_)) => {
+            a.partial_cmp(&b)
+        }
+        _ => Some(l.as_str().cmp(&r.as_str())),
+    };
+    let ordering = match ordering {
+        Some(v) => v,
+        None => return false,
+    };
+    match op {
+        CompareOp::Eq => ordering.is_eq(),
+        CompareOp::Ne => ordering.is_ne(),
+        CompareOp::Gt => ordering.is_gt(),
+        CompareOp::Ge => ordering.is_ge(),
+        CompareOp::Lt => ordering.is_lt(),
+        CompareOp::Le => ordering.is_le(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+fn tokenize(raw: &str) -> Result<Vec<Token>, String> {
+    // 长的运算符放在前面，保证优先匹配
+    const OPS: [&str; 11] = ["&&", "||", "==", "!=", ">=", "<=", "!~", ">", "<", "~", "!"];
+
+    let chars = raw.chars().collect::<Vec<char>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    'outer: while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        match c {
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '[' => tokens.push(Token::LBracket),
+            ']' => tokens.push(Token::RBracket),
+            ',' => tokens.push(Token::Comma),
+            '"' | '\'' => {
+                // 字符串，支持使用 \ 转义引号，其他的 \ 原样保留，方便书写正则
+                let mut buf = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    if chars[i] == '\\' && i + 1 < chars.len() && chars[i + 1] == c {
+                        i += 1;
+                    }
+                    buf.push(chars[i]);
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err("表达式中的字符串没有结束引号".to_owned());
+                }
+                tokens.push(Token::Str(buf));
+            }
+            _ if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let s = chars[start..i].iter().collect::<String>();
+                let n = s.parse::<f64>().map_err(|_| format!("无效的数字: {}", s))?;
+                tokens.push(Token::Number(n));
+                continue;
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | '-'))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+                continue;
+            }
+            _ => {
+                let rest = chars[i..].iter().collect::<String>();
+                for op in OPS {
+                    if rest.starts_with(op) {
+                        tokens.push(Token::Op(op));
+                        i += op.len();
+                        continue 'outer;
+                    }
+                }
+                return Err(format!("表达式中存在无法识别的字符: {}", c));
+            }
+        }
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.eat(&Token::Op("||")) {
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        while self.eat(&Token::Op("&&")) {
+            let right = self.parse_unary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.eat(&Token::Op("!")) {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat(&Token::LParen) {
+            let expr = self.parse_or()?;
+            if !self.eat(&Token::RParen) {
+                return Err("表达式缺少右括号".to_owned());
+            }
+            return Ok(expr);
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_operand()?;
+        let op = match self.peek() {
+            Some(Token::Op(op)) if !matches!(*op, "&&" | "||" | "!") => *op,
+            Some(Token::Ident(v)) if v == "in" => "in",
+            _ => return Ok(Expr::Truthy(left)),
+        };
+        self.pos += 1;
+
+        let expr = match op {
+            "in" => Expr::In(left, self.parse_list()?),
+            "~" | "!~" => match self.next() {
+                Some(Token::Str(pattern)) => {
+                    let re = Regex::new(&pattern)
+                        .map_err(|e| format!("表达式中的正则 {} 错误: {}", pattern, e))?;
+                    Expr::Regex(left, re, op == "!~")
+                }
+                _ => return Err(format!("{} 右侧必须是字符串", op)),
+            },
+            _ => {
+                let compare_op = match op {
+                    "==" => CompareOp::Eq,
+                    "!=" => CompareOp::Ne,
+                    ">" => CompareOp::Gt,
+                    ">=" => CompareOp::Ge,
+                    "<" => CompareOp::Lt,
+                    _ => CompareOp::Le,
+                };
+                Expr::Compare(left, compare_op, self.parse_operand()?)
+            }
+        };
+        Ok(expr)
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<Operand>, String> {
+        if !self.eat(&Token::LBracket) {
+            return Err("in 右侧必须是列表，例如 [200,403]".to_owned());
+        }
+        let mut list = vec![];
+        if self.eat(&Token::RBracket) {
+            return Ok(list);
+        }
+        loop {
+            list.push(self.parse_operand()?);
+            if self.eat(&Token::RBracket) {
+                return Ok(list);
+            }
+            if !self.eat(&Token::Comma) {
+                return Err("列表中缺少逗号或右括号".to_owned());
+            }
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Number(v)) => Ok(Operand::Number(v)),
+            Some(Token::Str(v)) => Ok(Operand::Str(v)),
+            Some(Token::Ident(v)) => Ok(Operand::Field(Field::parse(&v)?)),
+            Some(t) => Err(format!("表达式在 {:?} 附近有语法错误", t)),
+            None => Err("表达式不完整".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(status: u16, size: u64, body: &str) -> EnumResult {
+        EnumResult {
+            status_code: status,
+            url: "http://example.com/admin/login.php".to_owned(),
+            content: Some(body.to_owned()),
+            content_length: size,
+            ..Default::default()
+        }
+    }
+
+    fn eval(raw: &str, result: &EnumResult) -> bool {
+        Expr::parse(raw).unwrap().eval(result)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let r = result(404, 0, "");
+        // 解析为 status == 200 || (status == 404 && size > 10)
+        assert!(!eval("status == 200 || status == 404 && size > 10", &r));
+        assert!(eval("(status == 200 || status == 404) && size < 10", &r));
+        assert!(matches!(
+            Expr::parse("status == 1 || status == 2 && status == 3").unwrap(),
+            Expr::Or(_, _)
+        ));
+    }
+
+    #[test]
+    fn not_applies_to_regex() {
+        let r = result(200, 9, "not found");
+        assert!(!eval("!body ~ \"not found\"", &r));
+        assert!(eval("!body ~ \"welcome\"", &r));
+        assert!(eval("body !~ \"welcome\" && !(status != 200)", &r));
+        assert!(matches!(
+            Expr::parse("!body ~ \"x\"").unwrap(),
+            Expr::Not(e) if matches!(*e, Expr::Regex(_, _, false))
+        ));
+    }
+
+    #[test]
+    fn in_list() {
+        let r = result(403, 120, "");
+        assert!(eval("status in [200, 403]", &r));
+        assert!(!eval("status in [200,301]", &r));
+        assert!(!eval("status in []", &r));
+        assert!(eval("path in ['/index.php', \"/admin/login.php\"]", &r));
+    }
+
+    #[test]
+    fn compare_numbers_and_strings() {
+        let r = result(200, 120, "");
+        assert!(eval("size >= 120 && size <= 120 && size != 121", &r));
+        assert!(eval("status_line == ''", &r));
+        assert!(!eval("title", &r));
+        assert!(eval("url ~ '^http://example\\.com/'", &r));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for raw in [
+            "status ==",
+            "(status == 200",
+            "status == 200)",
+            "status in 200",
+            "status in [200 403]",
+            "body ~ 200",
+            "body ~ \"(\"",
+            "unknown == 1",
+            "header. == 1",
+            "status == \"200",
+            "status # 1",
+            "&& status",
+        ] {
+            assert!(Expr::parse(raw).is_err(), "{} should not parse", raw);
+        }
+    }
+
+    #[test]
+    fn need_body() {
+        assert!(Expr::parse("status == 200 || !body ~ 'x'")
+            .unwrap()
+            .need_body());
+        assert!(!Expr::parse("status in [200, size]").unwrap().need_body());
+    }
+}
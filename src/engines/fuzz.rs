@@ -0,0 +1,87 @@
+use crate::args_parser::AppArgs;
+use crate::engines::vhost;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+// 请求中被替换为任务的标记
+pub const MARKER: &str = "FUZZ";
+
+/**
+ * 请求中出现 FUZZ 标记的位置，host 表示 vhost 模式下任务替换 URL 中的主机名
+ * 使用标记时任务为替换标记的值，不再作为路径追加到 target 后面；标记只出现在 header、cookie 或请求体中时，URL 固定为 target
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FuzzPoints {
+    pub url: bool,
+    pub host: bool,
+    pub headers: bool,
+    pub cookie: bool,
+    pub body: bool,
+}
+
+impl FuzzPoints {
+    pub fn find(
+        target: &str,
+        vhost: bool,
+        headers: &[String],
+        cookie: Option<&String>,
+        body: Option<&String>,
+    ) -> Self {
+        Self {
+            url: target.contains(MARKER),
+            host: vhost,
+            headers: headers.iter().any(|it| it.contains(MARKER)),
+            cookie: cookie.is_some_and(|it| it.contains(MARKER)),
+            body: body.is_some_and(|it| it.contains(MARKER)),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.url || self.host || self.headers || self.cookie || self.body
+    }
+}
+
+/**
+ * 把任务渲染为请求的 URL，写入 buffer 中，buffer 原有的内容会被清空
+ */
+pub fn write_url(args: &AppArgs, value: &str, buffer: &mut String) {
+    buffer.clear();
+    if args.fuzz.host {
+        vhost::write_url(&args.target, value, buffer);
+    } else if args.fuzz.url {
+        buffer.push_str(&args.target.replace(MARKER, value));
+    } else {
+        buffer.push_str(&args.target);
+        if !args.fuzz.enabled() {
+            buffer.push_str(value);
+        }
+    }
+}
+
+pub fn request_url(args: &AppArgs, value: &str) -> String {
+    let mut url = String::new();
+    write_url(args, value, &mut url);
+    url
+}
+
+/**
+ * 替换 header、cookie 或请求体中的标记
+ */
+pub fn substitute(raw: &str, value: &str) -> String {
+    raw.replace(MARKER, value)
+}
+
+/**
+ * 生成一个不存在的值，用于基准页面、预检和 canary，vhost 模式下为根域名下一个随机的主机名
+ */
+pub fn random_value(args: &AppArgs) -> String {
+    let value: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    match &args.root_domain {
+        Some(root) if args.fuzz.host => format!("{}.{}", value.to_lowercase(), root),
+        _ => value,
+    }
+}
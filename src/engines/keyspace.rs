@@ -0,0 +1,358 @@
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+// 伪随机排列中 Feistel 网络的轮数
+const FEISTEL_ROUNDS: u64 = 4;
+
+/**
+ * 枚举模式的 keyspace，由字符池和后缀组成
+ * 长度为 length 的第 index 个任务与 multi_cartesian_product 生成的顺序一致：第一个字符变化最慢，后缀变化最快
+ * 指定 seed 时，编号先经过由 seed 决定的伪随机排列再渲染，区间和分片都作用在排列后的编号上
+ */
+#[derive(Debug)]
+pub struct Keyspace {
+    pool: Vec<char>,
+    suffixes: Vec<String>,
+    seed: Option<u64>,
+}
+
+impl Keyspace {
+    pub fn new(pool: Vec<char>, suffixes: Vec<String>) -> Self {
+        Self {
+            pool,
+            suffixes,
+            seed: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn suffixes(&self) -> &[String] {
+        &self.suffixes
+    }
+
+    /**
+     * 路径是否属于文件名长度在 lengths 范围内的 keyspace，不考虑分片
+     */
+    pub fn contains(&self, lengths: &RangeInclusive<usize>, path: &str) -> bool {
+        self.suffixes
+            .iter()
+            .filter_map(|suffix| path.strip_suffix(suffix.as_str()))
+            .any(|name| {
+                lengths.contains(&name.chars().count())
+                    && name.chars().all(|c| self.pool.contains(&c))
+            })
+    }
+
+    /**
+     * 长度为 length 的任务总数，参数解析时已经通过 total 确认不会超出 u64 的范围
+     */
+    pub fn size(&self, length: usize) -> u64 {
+        self.checked_size(length)
+            .expect("keyspace 的大小超出了 u64 的范围")
+    }
+
+    /**
+     * 长度为 length 的任务总数，超出 u64 的范围时返回 None
+     */
+    pub fn checked_size(&self, length: usize) -> Option<u64> {
+        (self.pool.len() as u64)
+            .checked_pow(u32::try_from(length).ok()?)?
+            .checked_mul(self.suffixes.len() as u64)
+    }
+
+    /**
+     * 文件名长度在 lengths 范围内的任务总数，超出 u64 的范围时返回 None
+     */
+    pub fn total(&self, lengths: RangeInclusive<usize>) -> Option<u64> {
+        lengths
+            .map(|length| self.checked_size(length))
+            .try_fold(0u64, |total, size| total.checked_add(size?))
+    }
+
+    /**
+     * 把长度为 length 的第 index 个任务渲染到 buffer 中，buffer 原有的内容会被清空
+     */
+    pub fn render(&self, length: usize, index: u64, buffer: &mut String) {
+        let index = match self.seed {
+            Some(seed) => self.permute(seed, length, index),
+            None => index,
+        };
+        let pool_length = self.pool.len() as u64;
+        let suffix_length = self.suffixes.len() as u64;
+        let mut rest = index / suffix_length;
+
+        buffer.clear();
+        if length > 0 {
+            let mut divisor = pool_length.pow(length as u32 - 1);
+            for _ in 0..length {
+                buffer.push(self.pool[((rest / divisor) % pool_length) as usize]);
+                rest %= divisor;
+                divisor = (divisor / pool_length).max(1);
+            }
+        }
+        buffer.push_str(&self.suffixes[(index % suffix_length) as usize]);
+    }
+
+    /**
+     * 基于 Feistel 网络的伪随机排列，把 [0, size) 一一映射到 [0, size)，不需要生成整个 keyspace
+     * 网络作用在不小于 size 的 2 的偶数次幂上，结果超出范围时继续迭代（cycle walking），直到落在范围内
+     */
+    fn permute(&self, seed: u64, length: usize, index: u64) -> u64 {
+        let size = self.size(length);
+        let bits = (u64::BITS - size.saturating_sub(1).leading_zeros()).max(2);
+        let half = bits.div_ceil(2);
+        let mask = (1u64 << half) - 1;
+        // 不同长度使用不同的排列
+        let key = seed ^ (length as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+        let mut value = index;
+        loop {
+            let mut left = value >> half;
+            let mut right = value & mask;
+            for round in 0..FEISTEL_ROUNDS {
+                let f = mix(right ^ key.wrapping_add(round)) & mask;
+                (left, right) = (right, left ^ f);
+            }
+            value = (left << half) | right;
+            if value < size {
+                return value;
+            }
+        }
+    }
+}
+
+/**
+ * splitmix64 的混淆函数
+ */
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/**
+ * keyspace 中长度为 length 的 [start, end) 区间，worker 在本地依次渲染出每个任务
+ */
+#[derive(Debug, Clone)]
+pub struct KeyRange {
+    pub keyspace: Arc<Keyspace>,
+    pub length: usize,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl KeyRange {
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /**
+     * 从区间开头切分出最多 count 个任务，当前区间只保留剩余的部分
+     */
+    pub fn take(&mut self, count: u64) -> KeyRange {
+        let end = self.end.min(self.start.saturating_add(count));
+        let part = KeyRange {
+            keyspace: Arc::clone(&self.keyspace),
+            length: self.length,
+            start: self.start,
+            end,
+        };
+        self.start = end;
+        part
+    }
+
+    /**
+     * 渲染区间中的下一个任务，区间已经处理完成时返回 false
+     */
+    pub fn next_into(&mut self, buffer: &mut String) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        self.keyspace.render(self.length, self.start, buffer);
+        self.start += 1;
+        true
+    }
+}
+
+/**
+ * 多台机器共同完成一次扫描时的分片，index 从 0 开始，命令行中从 1 开始
+ * 枚举模式下每种长度的 keyspace 被切分成 count 段连续的区间，字典模式下第 i 个任务属于第 i % count 个分片，
+ * 参数一致时所有分片恰好覆盖全部任务一次
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+impl Shard {
+    /**
+     * 解析 K/N 格式的分片参数，1 <= K <= N
+     */
+    pub fn parse(raw: &str) -> Result<Self, &'static str> {
+        let error = "shard 格式应为 K/N，并且 1 <= K <= N！";
+        let (k, n) = raw.split_once('/').ok_or(error)?;
+        let k = k.trim().parse::<u64>().map_err(|_| error)?;
+        let n = n.trim().parse::<u64>().map_err(|_| error)?;
+        if n == 0 || k == 0 || k > n {
+            return Err(error);
+        }
+        Ok(Self {
+            index: k - 1,
+            count: n,
+        })
+    }
+
+    pub fn is_sharded(&self) -> bool {
+        self.count > 1
+    }
+
+    /**
+     * 大小为 size 的 keyspace 中属于当前分片的连续区间
+     */
+    pub fn slice(&self, size: u64) -> Range<u64> {
+        let bound = |index: u64| (size as u128 * index as u128 / self.count as u128) as u64;
+        bound(self.index)..bound(self.index + 1)
+    }
+
+    /**
+     * 编号为 index 的任务是否属于当前分片
+     */
+    pub fn owns(&self, index: u64) -> bool {
+        index % self.count == self.index
+    }
+
+    /**
+     * 共 total 个任务时，属于当前分片的任务数
+     */
+    pub fn count_of(&self, total: u64) -> u64 {
+        (total + self.count - 1 - self.index) / self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn keyspace(seed: Option<u64>) -> Keyspace {
+        Keyspace::new(vec!['a', 'b', 'c'], vec![String::new(), ".php".to_owned()]).with_seed(seed)
+    }
+
+    #[test]
+    fn size_overflow_boundary() {
+        let pool = ('a'..='z').chain('A'..='Z').chain('0'..='9').collect();
+        let suffixes = vec![".html".to_owned(), ".php".to_owned(), ".zip".to_owned()];
+        let keyspace = Keyspace::new(pool, suffixes).with_seed(Some(7));
+
+        // 62^10 * 3 仍在 u64 的范围内，62^11 超出
+        assert_eq!(keyspace.checked_size(10), Some(62u64.pow(10) * 3));
+        assert_eq!(keyspace.checked_size(11), None);
+        assert!(keyspace.total(1..=10).is_some());
+        assert_eq!(keyspace.total(1..=11), None);
+        assert_eq!(keyspace.total(11..=11), None);
+
+        // 最大的合法长度可以正常渲染和排列
+        let mut buffer = String::new();
+        let size = keyspace.size(10);
+        let unseeded = Keyspace::new(keyspace.pool.clone(), keyspace.suffixes.clone());
+        unseeded.render(10, size - 1, &mut buffer);
+        assert_eq!(buffer, "9999999999.zip");
+        keyspace.render(10, size - 1, &mut buffer);
+        assert_eq!(buffer.split('.').next().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn permutation_is_bijection() {
+        for seed in [1, 42, u64::MAX] {
+            let keyspace = keyspace(Some(seed));
+            for length in 0..=4 {
+                let size = keyspace.size(length);
+                let indices = (0..size)
+                    .map(|index| keyspace.permute(seed, length, index))
+                    .collect::<HashSet<u64>>();
+                assert_eq!(indices.len() as u64, size);
+                assert!(indices.iter().all(|it| *it < size));
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_render_covers_keyspace() {
+        let ordered = keyspace(None);
+        let shuffled = keyspace(Some(7));
+        let mut buffer = String::new();
+        let render = |keyspace: &Keyspace, buffer: &mut String| {
+            (0..keyspace.size(3))
+                .map(|index| {
+                    keyspace.render(3, index, buffer);
+                    buffer.clone()
+                })
+                .collect::<Vec<String>>()
+        };
+        let expected = render(&ordered, &mut buffer);
+        let actual = render(&shuffled, &mut buffer);
+        assert_ne!(expected, actual);
+        assert_eq!(
+            expected.iter().collect::<HashSet<_>>(),
+            actual.iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(&expected[..3], ["aaa", "aaa.php", "aab"]);
+    }
+
+    #[test]
+    fn shards_cover_every_path_once() {
+        let keyspace = keyspace(None);
+        let mut buffer = String::new();
+        for count in 1..=7 {
+            let shards = (0..count)
+                .map(|index| Shard { index, count })
+                .collect::<Vec<Shard>>();
+            for length in 0..=3 {
+                let size = keyspace.size(length);
+                let mut paths = vec![];
+                for shard in &shards {
+                    for index in shard.slice(size) {
+                        keyspace.render(length, index, &mut buffer);
+                        paths.push(buffer.clone());
+                    }
+                }
+                assert_eq!(paths.len() as u64, size);
+                assert_eq!(paths.iter().collect::<HashSet<_>>().len() as u64, size);
+            }
+
+            // 字典模式下按编号取模分片
+            let total = 23;
+            for shard in &shards {
+                let owned = (0..total).filter(|it| shard.owns(*it)).count() as u64;
+                assert_eq!(owned, shard.count_of(total));
+            }
+            for index in 0..total {
+                assert_eq!(shards.iter().filter(|it| it.owns(index)).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_shard() {
+        assert_eq!(Shard::parse("2/3"), Ok(Shard { index: 1, count: 3 }));
+        for raw in ["0/3", "4/3", "1/0", "1", "a/b"] {
+            assert!(Shard::parse(raw).is_err());
+        }
+    }
+}
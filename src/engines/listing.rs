@@ -0,0 +1,50 @@
+use regex::Regex;
+use reqwest::Url;
+
+// 常见 Web 服务器目录列表页面的特征，比较时页面内容已转为小写
+const LISTING_SIGNATURES: [&str; 4] = [
+    // Apache、nginx
+    "<title>index of /",
+    "<h1>index of /",
+    // Python http.server
+    "<title>directory listing for /",
+    // IIS
+    "[to parent directory]",
+];
+
+/**
+ * 判断页面是否为目录列表
+ */
+pub fn is_dir_listing(content: &str) -> bool {
+    let content = content.to_lowercase();
+    LISTING_SIGNATURES.iter().any(|it| content.contains(it))
+}
+
+/**
+ * 从目录列表页面中解析出列出的文件和子目录，转换为相对于 target 的任务
+ * 只保留当前目录下的条目，父目录、排序链接等会被忽略
+ */
+pub fn extract_entries(content: &str, url: &str, target: &str) -> Vec<String> {
+    let href_re = Regex::new(r#"(?i)href\s*=\s*["']([^"'#?]+)["']"#).unwrap();
+    let base = match Url::parse(url) {
+        Ok(v) => v,
+        Err(_) => return vec![],
+    };
+
+    let mut entries = vec![];
+    for cap in href_re.captures_iter(content) {
+        let entry = match base.join(&cap[1]) {
+            Ok(v) => v.to_string(),
+            Err(_) => continue,
+        };
+        if entry.len() <= url.len() || !entry.starts_with(url) {
+            continue;
+        }
+        if let Some(task) = entry.strip_prefix(target) {
+            if !entries.iter().any(|it| it == task) {
+                entries.push(task.to_owned());
+            }
+        }
+    }
+    entries
+}
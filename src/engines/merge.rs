@@ -0,0 +1,53 @@
+use crate::args_parser::MergeArgs;
+use log::{error, info};
+use std::collections::BTreeSet;
+use std::process::exit;
+use tokio::fs::read_to_string;
+
+/**
+ * 合并多个分片的结果文件
+ * 完全相同的行只保留一条，结果按 URL 排序，URL 相同时按整行排序
+ */
+pub async fn merge(args: &MergeArgs) {
+    let mut lines: BTreeSet<(String, String)> = BTreeSet::new();
+    for input in &args.inputs {
+        // 缺少任何一个分片的结果都会导致合并结果不完整，直接退出
+        let content = match read_to_string(input).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("读取结果文件 {} 出错，错误：{:?}", input, e);
+                exit(-1);
+            }
+        };
+
+        let mut count = 0;
+        for line in content.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // 每行的格式为 <状态码> <URL> ...
+            let url = line.split_whitespace().nth(1).unwrap_or_default();
+            lines.insert((url.to_owned(), line.to_owned()));
+            count += 1;
+        }
+        info!("读取 {}，共 {} 条结果", input, count);
+    }
+
+    let mut content = String::new();
+    for (_, line) in &lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    match tokio::fs::write(&args.output, content).await {
+        Ok(_) => info!(
+            "合并了 {} 个文件，共 {} 条结果，已写入 {}",
+            args.inputs.len(),
+            lines.len(),
+            args.output
+        ),
+        Err(e) => {
+            error!("写入 {} 失败，error: {}", args.output, e);
+            exit(-1);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::args_parser::AppArgs;
+use crate::engines::fuzz;
+use crate::engines::{build_http_client, build_request};
+use log::{debug, info, warn};
+use reqwest::{Client, Method};
+
+/**
+ * 扫描开始前的预检，判断目标是否能正确处理 HEAD 请求
+ * 返回 true 表示需要整体回退为 GET 方法
+ */
+pub async fn preflight(args: &AppArgs) -> bool {
+    if args.request_method != "HEAD" {
+        return false;
+    }
+
+    let http_client = build_http_client(args, 0);
+
+    // 分别探测根目录和一个随机的不存在路径，fuzz 模式下只探测一个随机的值
+    let random_value = fuzz::random_value(args);
+    let mut probes = vec![];
+    if !args.fuzz.enabled() {
+        probes.push((args.target.clone(), String::new()));
+    }
+    probes.push((fuzz::request_url(args, &random_value), random_value));
+
+    for (url, value) in probes {
+        let head_code = match probe_status(&http_client, args, Method::HEAD, &url, &value).await {
+            Some(v) => v,
+            None => continue,
+        };
+
+        if head_code == 405 || head_code == 501 {
+            warn!(
+                "目标拒绝了 HEAD 请求，url: {}, code: {}，自动回退为 GET 方法",
+                url, head_code
+            );
+            return true;
+        }
+
+        let get_code = match probe_status(&http_client, args, Method::GET, &url, &value).await {
+            Some(v) => v,
+            None => continue,
+        };
+        debug!("preflight {} HEAD: {}, GET: {}", url, head_code, get_code);
+
+        if head_code != get_code {
+            warn!(
+                "目标对 HEAD 和 GET 返回了不同的状态码，url: {}, HEAD: {}, GET: {}，自动回退为 GET 方法",
+                url, head_code, get_code
+            );
+            return true;
+        }
+    }
+
+    info!("HEAD 预检通过");
+    false
+}
+
+async fn probe_status(
+    http_client: &Client,
+    args: &AppArgs,
+    method: Method,
+    url: &str,
+    value: &str,
+) -> Option<u16> {
+    match build_request(http_client, args, method.clone(), url, value)
+        .send()
+        .await
+    {
+        Ok(r) => Some(r.status().as_u16()),
+        Err(e) => {
+            warn!(
+                "预检请求失败，method: {}, url: {}, error: {}",
+                method, url, e
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::context::{AppContext, Progress};
+use crate::engines::keyspace::KeyRange;
+use crate::engines::seen::SeenFilter;
+use async_channel::{Receiver, Sender};
+use itertools::Itertools;
+use log::{debug, info};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/**
+ * 任务队列
+ * builder 生成的任务通过有界通道传递，扫描过程中额外产生的任务（目录列表中的条目、重新入队的任务等）通过无界通道传递，
+ * 避免 worker 向已满的通道中放入任务时互相等待
+ * pending 为已经放入队列但尚未处理完成的任务数量，builder 结束并且 pending 归零后关闭通道，worker 和 saver 随之退出
+ * 枚举模式下 builder 放入的是 keyspace 区间，一个区间计为一个 pending，由 worker 在本地渲染出每个路径
+ * 任务总数记录到 progress 中，已完成的数量由 worker 按路径记录，用于展示进度
+ * 每个任务放入队列时分配一个编号，开启 acks 时处理完成的任务编号会发送到 acks 通道，用于分布式模式下向协调者确认
+ * seen 记录字典模式以及目录列表生成过的任务，用于全局去重
+ */
+#[derive(Debug)]
+pub struct TaskQueue {
+    task_tx: Sender<(u64, Task)>,
+    task_rx: Receiver<(u64, Task)>,
+    extra_tx: Sender<(u64, String)>,
+    extra_rx: Receiver<(u64, String)>,
+    next_id: AtomicU64,
+    acks: Option<Sender<u64>>,
+    seen: std::sync::Mutex<SeenFilter>,
+    pending: AtomicUsize,
+    builder_done: AtomicBool,
+    closed: Notify,
+    progress: Arc<Progress>,
+}
+
+/**
+ * 队列中的任务，单个路径或者 keyspace 中的一段区间
+ */
+#[derive(Debug)]
+pub enum Task {
+    Path(String),
+    Range(KeyRange),
+}
+
+impl TaskQueue {
+    pub fn new(capacity: usize, progress: Arc<Progress>) -> Self {
+        let (task_tx, task_rx) = async_channel::bounded(capacity);
+        let (extra_tx, extra_rx) = async_channel::unbounded();
+        Self {
+            task_tx,
+            task_rx,
+            extra_tx,
+            extra_rx,
+            next_id: AtomicU64::new(0),
+            acks: None,
+            seen: std::sync::Mutex::new(SeenFilter::default()),
+            pending: AtomicUsize::new(0),
+            builder_done: AtomicBool::new(false),
+            closed: Notify::new(),
+            progress,
+        }
+    }
+
+    /**
+     * 创建开启 acks 的队列，返回处理完成的任务编号的接收端
+     */
+    pub fn with_acks(capacity: usize, progress: Arc<Progress>) -> (Self, Receiver<u64>) {
+        let (ack_tx, ack_rx) = async_channel::unbounded();
+        let mut task_queue = Self::new(capacity, progress);
+        task_queue.acks = Some(ack_tx);
+        (task_queue, ack_rx)
+    }
+
+    /**
+     * builder 预先计算出的任务总数
+     */
+    pub fn expect(&self, total: u64) {
+        self.progress.total.fetch_add(total, Ordering::Relaxed);
+    }
+
+    /**
+     * 实际生成的任务少于预计的数量（例如跳过了重复的任务）时，修正任务总数
+     */
+    pub fn expect_fewer(&self, count: u64) {
+        self.progress.total.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    /**
+     * 按预计的任务数量重新创建去重使用的过滤器，需要在生成任务之前调用
+     */
+    pub fn reserve_seen(&self, expected: u64) {
+        *self.seen.lock().unwrap() = SeenFilter::new(expected);
+    }
+
+    /**
+     * 记录一个任务，之前已经生成过时返回 false
+     */
+    pub fn first_seen(&self, task: &str) -> bool {
+        self.seen.lock().unwrap().insert(task)
+    }
+
+    /**
+     * builder 放入一个任务并返回任务编号，通道已满时等待，队列已关闭（扫描被终止）时返回 None
+     */
+    pub async fn send(&self, task: Task) -> Option<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if self.task_tx.send((id, task)).await.is_err() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(id)
+    }
+
+    /**
+     * 放入额外产生的任务，不会等待，队列已关闭时丢弃
+     */
+    pub fn push(&self, tasks: impl IntoIterator<Item = String>) {
+        let count = self.push_extra(tasks);
+        self.progress.total.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /**
+     * 重新放入已经处理过的任务，例如被 WAF 拦截的任务，不计入任务总数，已完成的数量相应减少
+     */
+    pub fn requeue(&self, tasks: impl IntoIterator<Item = String>) {
+        let count = self.push_extra(tasks);
+        self.progress.done.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    /**
+     * 重新放入还没有处理完成的任务，例如断开连接的远程 worker 没有确认的任务，任务总数和已完成的数量都不变
+     */
+    pub fn resend(&self, tasks: impl IntoIterator<Item = String>) {
+        self.push_extra(tasks);
+    }
+
+    fn push_extra(&self, tasks: impl IntoIterator<Item = String>) -> u64 {
+        let mut count = 0;
+        for task in tasks {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            if self.extra_tx.try_send((id, task)).is_err() {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /**
+     * 取出一个任务及其编号，额外产生的任务优先，队列关闭后返回 None
+     */
+    pub async fn recv(&self) -> Option<(u64, Task)> {
+        tokio::select! {
+            biased;
+            Ok((id, task)) = self.extra_rx.recv() => Some((id, Task::Path(task))),
+            Ok(task) = self.task_rx.recv() => Some(task),
+            else => None,
+        }
+    }
+
+    /**
+     * worker 处理完成一个任务（单个路径或整个区间），返回所有任务是否都已处理完成
+     */
+    pub fn task_done(&self, id: u64) -> bool {
+        if let Some(acks) = &self.acks {
+            let _ = acks.try_send(id);
+        }
+        self.pending.fetch_sub(1, Ordering::SeqCst) == 1 && self.builder_done.load(Ordering::SeqCst)
+    }
+
+    /**
+     * builder 生成任务结束，返回所有任务是否都已处理完成
+     */
+    pub fn builder_done(&self) -> bool {
+        self.builder_done.store(true, Ordering::SeqCst);
+        self.pending.load(Ordering::SeqCst) == 0
+    }
+
+    /**
+     * 所有任务都已处理完成，对重试全部失败的任务再单独尝试一轮，已经尝试过时关闭队列
+     */
+    pub fn finish(&self, app_context: &AppContext) {
+        let mut failures = app_context.failures.lock().unwrap();
+        // builder 和最后一个 worker 可能同时调用，重新入队后不再关闭
+        if self.pending.load(Ordering::SeqCst) != 0 || self.is_closed() {
+            return;
+        }
+
+        if !failures.retry_pass && !failures.failed.is_empty() {
+            failures.retry_pass = true;
+            let tasks = failures
+                .failed
+                .drain()
+                .map(|it| it.0)
+                .sorted()
+                .collect::<Vec<String>>();
+            info!("重新尝试 {} 个失败的任务", tasks.len());
+            self.push(tasks);
+            return;
+        }
+
+        debug!("all tasks done, close task queue.");
+        self.close();
+    }
+
+    /**
+     * 关闭队列，未处理的任务会被丢弃
+     */
+    pub fn close(&self) {
+        self.task_tx.close();
+        self.extra_tx.close();
+        self.closed.notify_waiters();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.task_tx.is_closed()
+    }
+
+    /**
+     * 等待队列关闭
+     */
+    pub async fn closed(&self) {
+        let notified = self.closed.notified();
+        if self.is_closed() {
+            return;
+        }
+        notified.await;
+    }
+}
@@ -0,0 +1,138 @@
+use crate::args_parser::AppArgs;
+use crate::context::RedirectHop;
+use crate::engines::build_request;
+use reqwest::header::{
+    AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION,
+    PROXY_AUTHORIZATION,
+};
+use reqwest::{Client, Method, Request, Response, StatusCode, Url};
+
+/**
+ * 跳转策略，HTTP Client 本身不跟随跳转，由 send_with_redirects 手动处理，以便记录跳转链
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedirectPolicy {
+    // 不跟随跳转，直接记录 3xx 响应
+    Off,
+    // 跟随所有跳转
+    #[default]
+    Follow,
+    // 只跟随同一个 host 内的跳转
+    SameHost,
+}
+
+impl RedirectPolicy {
+    pub fn parse(raw: &str) -> Result<Self, &'static str> {
+        match raw.to_lowercase().as_str() {
+            "off" => Ok(RedirectPolicy::Off),
+            "follow" => Ok(RedirectPolicy::Follow),
+            "same-host" => Ok(RedirectPolicy::SameHost),
+            _ => Err("redirect 只能为 off、follow 或 same-host！"),
+        }
+    }
+}
+
+/**
+ * 获取 3xx 响应中的跳转地址，相对地址会基于当前 URL 转换为绝对地址
+ */
+pub fn get_location(response: &Response) -> Option<Url> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    response.url().join(location).ok()
+}
+
+/**
+ * 两个 URL 的 host 和端口是否相同
+ */
+fn same_host(a: &Url, b: &Url) -> bool {
+    a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/**
+ * 去掉请求中的 Cookie、认证信息以及 CLI 参数中指定的 header，避免跳转到其他 host 时泄露
+ */
+fn strip_credentials(request: &mut Request, args: &AppArgs) {
+    let headers = request.headers_mut();
+    headers.remove(COOKIE);
+    headers.remove(AUTHORIZATION);
+    headers.remove(PROXY_AUTHORIZATION);
+    for header in &args.headers {
+        if let Some((key, _)) = header.split_once(':') {
+            headers.remove(key.trim());
+        }
+    }
+}
+
+/**
+ * 去掉请求体以及描述请求体的 header，跳转把方法切换为 GET 时使用
+ */
+fn strip_body(request: &mut Request) {
+    *request.body_mut() = None;
+    let headers = request.headers_mut();
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(CONTENT_ENCODING);
+}
+
+/**
+ * 发送请求，并根据跳转策略手动跟随跳转，返回最终的响应以及经过的跳转链
+ * value 为替换请求中 FUZZ 标记的值，跟随跳转时同样替换，跳转到其他 host 或端口时不再携带 Cookie 和自定义 header
+ * 跳转切换了请求方法时不再携带请求体
+ */
+pub async fn send_with_redirects(
+    http_client: &Client,
+    args: &AppArgs,
+    method: Method,
+    url: &str,
+    value: &str,
+) -> Result<(Response, Vec<RedirectHop>), reqwest::Error> {
+    let mut chain = vec![];
+    let original = method.clone();
+    let mut method = method;
+    let mut response = build_request(http_client, args, method.clone(), url, value)
+        .send()
+        .await?;
+
+    if args.redirect_policy == RedirectPolicy::Off {
+        return Ok((response, chain));
+    }
+
+    let origin = response.url().clone();
+    while chain.len() < args.max_redirects {
+        let next = match get_location(&response) {
+            Some(v) => v,
+            None => break,
+        };
+
+        if args.redirect_policy == RedirectPolicy::SameHost && !same_host(&next, response.url()) {
+            break;
+        }
+
+        // 307 和 308 需要保持原来的方法，其他跳转除 HEAD 外都切换为 GET
+        let status = response.status();
+        if status != StatusCode::TEMPORARY_REDIRECT
+            && status != StatusCode::PERMANENT_REDIRECT
+            && method != Method::HEAD
+        {
+            method = Method::GET;
+        }
+
+        chain.push(RedirectHop {
+            status_code: status.as_u16(),
+            location: next.to_string(),
+        });
+        let mut request =
+            build_request(http_client, args, method.clone(), next.as_str(), value).build()?;
+        if !same_host(&next, &origin) {
+            strip_credentials(&mut request, args);
+        }
+        if method != original {
+            strip_body(&mut request);
+        }
+        response = http_client.execute(request).await?;
+    }
+
+    Ok((response, chain))
+}
@@ -0,0 +1,338 @@
+use crate::context::EnumResult;
+use log::error;
+use regex::Regex;
+use std::fs::read_to_string;
+
+/**
+ * 规则作用的位置
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleTarget {
+    // 响应体
+    Body,
+    // 状态行，例如 HTTP/1.1 200 OK
+    Status,
+    // 指定的响应头，只匹配响应头的值
+    Header(String),
+    // 所有响应头，每行一个 Name: Value
+    Headers,
+    // 最终的跳转地址
+    Location,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RuleLogic {
+    And,
+    #[default]
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub target: RuleTarget,
+    pub regex: Regex,
+}
+
+impl Rule {
+    /**
+     * 解析单条规则，格式为 [target:]regex
+     * target 可以是 body、status、location、headers 或 header:<Name>，省略时默认为 body
+     */
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let (target, pattern) = if let Some(v) = raw.strip_prefix("body:") {
+            (RuleTarget::Body, v)
+        } else if let Some(v) = raw.strip_prefix("status:") {
+            (RuleTarget::Status, v)
+        } else if let Some(v) = raw.strip_prefix("location:") {
+            (RuleTarget::Location, v)
+        } else if let Some(v) = raw.strip_prefix("headers:") {
+            (RuleTarget::Headers, v)
+        } else if let Some(v) = raw.strip_prefix("header:") {
+            match v.split_once(':') {
+                Some((name, pattern)) if !name.trim().is_empty() => {
+                    (RuleTarget::Header(name.trim().to_lowercase()), pattern)
+                }
+                _ => return Err(format!("header 规则缺少响应头名称: {}", raw)),
+            }
+        } else {
+            (RuleTarget::Body, raw)
+        };
+
+        let regex = Regex::new(pattern).map_err(|e| format!("规则 {} 正则错误: {}", raw, e))?;
+        Ok(Self { target, regex })
+    }
+
+    pub fn is_match(&self, result: &EnumResult) -> bool {
+        match &self.target {
+            RuleTarget::Body => result
+                .content
+                .as_ref()
+                .is_some_and(|it| self.regex.is_match(it)),
+            RuleTarget::Status => self.regex.is_match(&result.status_line),
+            RuleTarget::Location => result
+                .location
+                .as_ref()
+                .is_some_and(|it| self.regex.is_match(it)),
+            RuleTarget::Header(name) => result
+                .headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|it| it.to_str().ok())
+                .any(|it| self.regex.is_match(it)),
+            RuleTarget::Headers => {
+                let headers = result
+                    .headers
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v.to_str().unwrap_or_default()))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                self.regex.is_match(&headers)
+            }
+        }
+    }
+}
+
+/**
+ * match 规则与 filter 规则的集合
+ * 结果需要满足 match 规则（未设置时视为满足），并且不满足 filter 规则才会被记录
+ */
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    pub match_rules: Vec<Rule>,
+    pub filter_rules: Vec<Rule>,
+    pub logic: RuleLogic,
+}
+
+impl RuleSet {
+    /**
+     * 读取规则文件，每行一条规则，格式为 match <rule> 或 filter <rule>
+     * 另外支持 logic and / logic or 指定组合方式，# 开头的行为注释
+     */
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let content = read_to_string(path).map_err(|e| format!("读取规则文件出错: {}", e))?;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (kind, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            match kind {
+                "match" => self.match_rules.push(Rule::parse(value)?),
+                "filter" => self.filter_rules.push(Rule::parse(value)?),
+                "logic" => self.logic = parse_logic(value)?,
+                _ => return Err(format!("无法识别的规则: {}", line)),
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * 是否有规则需要用到响应体
+     */
+    pub fn need_body(&self) -> bool {
+        self.match_rules
+            .iter()
+            .chain(self.filter_rules.iter())
+            .any(|it| it.target == RuleTarget::Body)
+    }
+
+    /**
+     * 判断结果是否应该被保留
+     */
+    pub fn accept(&self, result: &EnumResult) -> bool {
+        if !self.match_rules.is_empty() && !self.combine(&self.match_rules, result) {
+            return false;
+        }
+        if !self.filter_rules.is_empty() && self.combine(&self.filter_rules, result) {
+            return false;
+        }
+        true
+    }
+
+    fn combine(&self, rules: &[Rule], result: &EnumResult) -> bool {
+        match self.logic {
+            RuleLogic::And => rules.iter().all(|it| it.is_match(result)),
+            RuleLogic::Or => rules.iter().any(|it| it.is_match(result)),
+        }
+    }
+}
+
+pub fn parse_logic(raw: &str) -> Result<RuleLogic, String> {
+    match raw.to_lowercase().as_str() {
+        "and" => Ok(RuleLogic::And),
+        "or" => Ok(RuleLogic::Or),
+        _ => Err(format!("规则组合方式只能为 and 或 or: {}", raw)),
+    }
+}
+
+/**
+ * 根据 CLI 参数构建规则集，出错时记录详细日志
+ */
+pub fn build_rule_set(
+    match_rules: &[String],
+    filter_rules: &[String],
+    logic: &str,
+    rules_file: Option<&String>,
+) -> Result<RuleSet, &'static str> {
+    let build = || -> Result<RuleSet, String> {
+        let mut rule_set = RuleSet {
+            logic: parse_logic(logic)?,
+            ..Default::default()
+        };
+        if let Some(path) = rules_file {
+            rule_set.load_file(path)?;
+        }
+        for it in match_rules {
+            rule_set.match_rules.push(Rule::parse(it)?);
+        }
+        for it in filter_rules {
+            rule_set.filter_rules.push(Rule::parse(it)?);
+        }
+        Ok(rule_set)
+    };
+
+    build().map_err(|e| {
+        error!("{}", e);
+        "规则设置错误！"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, SERVER, SET_COOKIE};
+
+    fn result() -> EnumResult {
+        let mut headers = HeaderMap::new();
+        headers.insert(SERVER, HeaderValue::from_static("nginx/1.18.0"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("PHPSESSID=abc"));
+        EnumResult {
+            status_code: 302,
+            status_line: "HTTP/1.1 302 Found".to_owned(),
+            headers,
+            location: Some("https://example.com/login".to_owned()),
+            content: Some("<title>Admin Login</title>".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn rule_set(match_rules: &[&str], filter_rules: &[&str], logic: RuleLogic) -> RuleSet {
+        RuleSet {
+            match_rules: match_rules
+                .iter()
+                .map(|it| Rule::parse(it).unwrap())
+                .collect(),
+            filter_rules: filter_rules
+                .iter()
+                .map(|it| Rule::parse(it).unwrap())
+                .collect(),
+            logic,
+        }
+    }
+
+    #[test]
+    fn parse_targets() {
+        let cases = [
+            ("Admin", RuleTarget::Body),
+            ("body:status:", RuleTarget::Body),
+            ("status:302", RuleTarget::Status),
+            ("location:/login$", RuleTarget::Location),
+            ("headers:(?i)^server:", RuleTarget::Headers),
+            (
+                "header: Set-Cookie :PHPSESSID",
+                RuleTarget::Header("set-cookie".to_owned()),
+            ),
+            ("header:X-A:b:c", RuleTarget::Header("x-a".to_owned())),
+        ];
+        for (raw, target) in cases {
+            assert_eq!(Rule::parse(raw).unwrap().target, target, "{}", raw);
+        }
+        // 只去掉 target 前缀，剩余部分原样作为正则
+        assert_eq!(
+            Rule::parse("body:status:").unwrap().regex.as_str(),
+            "status:"
+        );
+        assert_eq!(Rule::parse("header:X-A:b:c").unwrap().regex.as_str(), "b:c");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for raw in [
+            "header:",
+            "header::x",
+            "header: :x",
+            "header:Server",
+            "status:(",
+            "[a-",
+        ] {
+            assert!(Rule::parse(raw).is_err(), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn match_each_target() {
+        let r = result();
+        let is_match = |raw: &str| Rule::parse(raw).unwrap().is_match(&r);
+        assert!(is_match("Admin Login"));
+        assert!(is_match("status:^HTTP/1\\.1 302"));
+        assert!(!is_match("status:200"));
+        assert!(is_match("location:/login$"));
+        assert!(is_match("header:server:^nginx"));
+        // 同名的多个响应头任意一个匹配即可，只匹配值
+        assert!(is_match("header:Set-Cookie:^PHPSESSID="));
+        assert!(!is_match("header:Set-Cookie:^set-cookie"));
+        assert!(!is_match("header:X-Powered-By:.*"));
+        assert!(is_match("headers:(?m)^set-cookie: PHPSESSID"));
+
+        // 没有响应体或跳转地址时不匹配
+        let empty = EnumResult::default();
+        assert!(!Rule::parse(".*").unwrap().is_match(&empty));
+        assert!(!Rule::parse("location:.*").unwrap().is_match(&empty));
+    }
+
+    #[test]
+    fn accept_combines_match_and_filter() {
+        let r = result();
+        assert!(RuleSet::default().accept(&r));
+        assert!(rule_set(&["Admin"], &[], RuleLogic::Or).accept(&r));
+        assert!(!rule_set(&["Welcome"], &[], RuleLogic::Or).accept(&r));
+        // filter 规则命中时丢弃，即使 match 规则也命中
+        assert!(!rule_set(&["Admin"], &["status:302"], RuleLogic::Or).accept(&r));
+        assert!(rule_set(&[], &["status:404"], RuleLogic::Or).accept(&r));
+
+        // and 要求全部命中，or 只需要任意一条
+        let rules = ["Admin", "status:200"];
+        assert!(rule_set(&rules, &[], RuleLogic::Or).accept(&r));
+        assert!(!rule_set(&rules, &[], RuleLogic::And).accept(&r));
+        assert!(rule_set(&[], &rules, RuleLogic::And).accept(&r));
+        assert!(!rule_set(&[], &rules, RuleLogic::Or).accept(&r));
+    }
+
+    #[test]
+    fn load_rules_file() {
+        let path = std::env::temp_dir().join(format!("enum-dir-rules-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "# 注释\n\nlogic and\nmatch status:302\nmatch\tAdmin\nfilter header:Server:IIS\n",
+        )
+        .unwrap();
+        let mut rule_set = RuleSet::default();
+        rule_set.load_file(path).unwrap();
+        assert_eq!(rule_set.logic, RuleLogic::And);
+        assert_eq!(rule_set.match_rules.len(), 2);
+        assert_eq!(rule_set.filter_rules.len(), 1);
+        assert!(rule_set.need_body());
+        assert!(rule_set.accept(&result()));
+
+        for content in ["matches Admin\n", "logic xor\n", "filter status:(\n"] {
+            std::fs::write(path, content).unwrap();
+            assert!(RuleSet::default().load_file(path).is_err(), "{}", content);
+        }
+        std::fs::remove_file(path).unwrap();
+        assert!(RuleSet::default().load_file(path).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::args_parser::AppArgs;
+use log::warn;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use url::{Position, Url};
+
+// 期望的误判率，误判时会跳过一个实际上没有扫描过的任务
+const FALSE_POSITIVE_RATE: f64 = 1e-6;
+// 布隆过滤器最多占用的内存
+const MAX_BYTES: u64 = 64 * 1024 * 1024;
+// 未指定预计数量时（例如只用于目录列表中的条目）的容量
+const DEFAULT_CAPACITY: u64 = 1 << 16;
+
+/**
+ * 记录已经生成过的任务，基于布隆过滤器，内存占用不超过 MAX_BYTES
+ * 按预计的任务数量计算位数和哈希函数数量，超过内存上限时误判率会升高
+ */
+#[derive(Debug)]
+pub struct SeenFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl Default for SeenFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl SeenFilter {
+    pub fn new(expected: u64) -> Self {
+        let expected = expected.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let wanted = (-expected * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as u64;
+        let bits = wanted.clamp(1024, MAX_BYTES * 8);
+        if bits < wanted {
+            warn!(
+                "预计的任务数量过多，去重使用的内存限制为 {}MB，可能会误判部分任务为重复",
+                MAX_BYTES / 1024 / 1024
+            );
+        }
+        let hashes = ((bits as f64 / expected) * ln2).round().clamp(1.0, 30.0) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(64) as usize],
+            hashes,
+        }
+    }
+
+    /**
+     * 记录一个任务，之前已经记录过时返回 false
+     */
+    pub fn insert(&mut self, task: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        task.hash(&mut hasher);
+        let h1 = hasher.finish();
+        // 双重哈希，由两个哈希值组合出 hashes 个位置
+        let h2 = h1.rotate_left(32).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let total_bits = self.bits.len() as u64 * 64;
+
+        let mut inserted = false;
+        for i in 0..self.hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % total_bits;
+            let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+            if self.bits[word] & mask == 0 {
+                self.bits[word] |= mask;
+                inserted = true;
+            }
+        }
+        inserted
+    }
+}
+
+/**
+ * 把等价的路径写法统一为同一种形式：
+ * 去掉首尾空白和开头的 /，合并连续的 /，去掉 ./，百分号编码统一为大写
+ * 结尾的 / 会保留，.. 不做处理
+ */
+pub fn normalize_task(task: &str) -> String {
+    let task = task.trim();
+    let trailing_slash = task.ends_with('/');
+
+    let mut result = String::with_capacity(task.len());
+    for segment in task.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        if !result.is_empty() {
+            result.push('/');
+        }
+        push_segment(&mut result, segment);
+    }
+    if trailing_slash && !result.is_empty() {
+        result.push('/');
+    }
+    result
+}
+
+/**
+ * 去重使用的 key，为统一写法后实际请求的路径和参数，任务本身保持原样发送
+ * URL 解析时会处理 ../、%2e 等点号路径，解析后相同的任务实际请求的是同一个地址
+ * fuzz 模式下任务不是路径，直接使用任务本身
+ */
+pub fn seen_key(args: &AppArgs, task: &str) -> String {
+    // 主机名不区分大小写
+    if args.fuzz.host {
+        return task.to_ascii_lowercase();
+    }
+    if args.fuzz.enabled() {
+        return task.to_owned();
+    }
+    match Url::parse(&format!("{}{}", args.target, normalize_task(task))) {
+        Ok(url) => url[Position::BeforePath..].to_owned(),
+        Err(_) => task.to_owned(),
+    }
+}
+
+fn push_segment(result: &mut String, segment: &str) {
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        result.push(c);
+        if c != '%' {
+            continue;
+        }
+        // 百分号后面的两位十六进制数统一为大写
+        for _ in 0..2 {
+            match chars.peek() {
+                Some(h) if h.is_ascii_hexdigit() => {
+                    result.push(h.to_ascii_uppercase());
+                    chars.next();
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_equivalent_paths() {
+        let cases = [
+            ("admin", "admin"),
+            ("/admin", "admin"),
+            ("  /admin/  ", "admin/"),
+            ("a//b///c", "a/b/c"),
+            ("./a/./b/.", "a/b"),
+            ("a/b/", "a/b/"),
+            ("a/../b", "a/../b"),
+            ("%2fetc%2Fpasswd%e4", "%2Fetc%2Fpasswd%E4"),
+            ("100%", "100%"),
+            ("%zz%a", "%zz%A"),
+            ("/", ""),
+            ("./", ""),
+            ("", ""),
+        ];
+        for (task, expected) in cases {
+            assert_eq!(normalize_task(task), expected, "task: {:?}", task);
+        }
+    }
+
+    #[test]
+    fn normalize_is_idempotent() {
+        for task in ["//a/./b//", "%2e%2E/x", " .a/..b/ ", "a%2f/"] {
+            let once = normalize_task(task);
+            assert_eq!(normalize_task(&once), once);
+        }
+    }
+
+    #[test]
+    fn filter_reports_first_insert() {
+        let mut filter = SeenFilter::new(1000);
+        for i in 0..1000 {
+            assert!(filter.insert(&format!("task{}", i)));
+        }
+        for i in 0..1000 {
+            assert!(!filter.insert(&format!("task{}", i)));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::engines::baseline::baseline_key;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+// 延迟统计的精度为 1ms，超过 MAX_LATENCY_MS 的请求统一计入最后一个桶
+const MAX_LATENCY_MS: u64 = 60 * 1000;
+// 输出的延迟百分位
+const PERCENTILES: [f64; 4] = [50.0, 90.0, 95.0, 99.0];
+
+/**
+ * 请求失败的原因分类
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    Timeout,
+    ConnectionReset,
+    ConnectionRefused,
+    Tls,
+    Dns,
+    Decode,
+    Other,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::ConnectionReset => "connection reset",
+            ErrorKind::ConnectionRefused => "connection refused",
+            ErrorKind::Tls => "tls",
+            ErrorKind::Dns => "dns",
+            ErrorKind::Decode => "decode",
+            ErrorKind::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ErrorKind {
+    /**
+     * 根据 reqwest 的错误以及其底层错误的描述进行分类
+     */
+    pub fn classify(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return ErrorKind::Timeout;
+        }
+        if e.is_decode() || e.is_body() {
+            return ErrorKind::Decode;
+        }
+
+        let mut messages = vec![];
+        let mut source: Option<&dyn Error> = e.source();
+        while let Some(err) = source {
+            messages.push(err.to_string().to_lowercase());
+            source = err.source();
+        }
+        let message = messages.join(" | ");
+
+        if message.contains("dns error") || message.contains("failed to lookup address") {
+            ErrorKind::Dns
+        } else if message.contains("tls")
+            || message.contains("certificate")
+            || message.contains("handshake")
+        {
+            ErrorKind::Tls
+        } else if message.contains("timed out") {
+            ErrorKind::Timeout
+        } else if message.contains("connection refused") {
+            ErrorKind::ConnectionRefused
+        } else if message.contains("connection reset")
+            || message.contains("connection closed")
+            || message.contains("broken pipe")
+            || message.contains("unexpected eof")
+        {
+            ErrorKind::ConnectionReset
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/**
+ * 扫描过程中的统计信息，扫描结束时输出汇总
+ * 每个 worker 在本地统计，退出时合并到共享的统计中，发送请求时不需要加锁
+ */
+#[derive(Debug)]
+pub struct Stats {
+    start: Instant,
+    // 发出的请求数，包括失败和重试的请求
+    requests: u64,
+    // 跳过的重复任务数
+    duplicates: u64,
+    by_status: BTreeMap<u16, u64>,
+    by_suffix: BTreeMap<String, u64>,
+    by_error: BTreeMap<ErrorKind, u64>,
+    // 每个桶为 1ms 的延迟直方图，只记录出现过的桶
+    latency_buckets: BTreeMap<u64, u64>,
+    latency_total: Duration,
+    latency_count: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            requests: 0,
+            duplicates: 0,
+            by_status: BTreeMap::new(),
+            by_suffix: BTreeMap::new(),
+            by_error: BTreeMap::new(),
+            latency_buckets: BTreeMap::new(),
+            latency_total: Duration::ZERO,
+            latency_count: 0,
+        }
+    }
+}
+
+impl Stats {
+    /**
+     * 记录一个成功的响应
+     */
+    pub fn record_response(&mut self, task: &str, status_code: u16, latency: Duration) {
+        self.requests += 1;
+        *self.by_status.entry(status_code).or_insert(0) += 1;
+
+        let suffix = baseline_key(task).1;
+        *self.by_suffix.entry(suffix).or_insert(0) += 1;
+
+        let bucket = (latency.as_millis() as u64).min(MAX_LATENCY_MS);
+        *self.latency_buckets.entry(bucket).or_insert(0) += 1;
+        self.latency_total += latency;
+        self.latency_count += 1;
+    }
+
+    /**
+     * 记录一个失败的请求
+     */
+    pub fn record_error(&mut self, e: &reqwest::Error) {
+        self.requests += 1;
+        *self.by_error.entry(ErrorKind::classify(e)).or_insert(0) += 1;
+    }
+
+    /**
+     * 记录跳过的重复任务
+     */
+    pub fn record_duplicates(&mut self, count: u64) {
+        self.duplicates += count;
+    }
+
+    /**
+     * 合并另一个 worker 的统计，耗时以当前统计的开始时间为准
+     */
+    pub fn merge(&mut self, other: Stats) {
+        self.requests += other.requests;
+        self.duplicates += other.duplicates;
+        for (code, count) in other.by_status {
+            *self.by_status.entry(code).or_insert(0) += count;
+        }
+        for (suffix, count) in other.by_suffix {
+            *self.by_suffix.entry(suffix).or_insert(0) += count;
+        }
+        for (kind, count) in other.by_error {
+            *self.by_error.entry(kind).or_insert(0) += count;
+        }
+        for (bucket, count) in other.latency_buckets {
+            *self.latency_buckets.entry(bucket).or_insert(0) += count;
+        }
+        self.latency_total += other.latency_total;
+        self.latency_count += other.latency_count;
+    }
+
+    fn elapsed(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn requests_per_second(&self) -> f64 {
+        let elapsed = self.elapsed();
+        if elapsed > 0.0 {
+            self.requests as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn average_latency(&self) -> u64 {
+        if self.latency_count == 0 {
+            return 0;
+        }
+        (self.latency_total.as_secs_f64() * 1000.0 / self.latency_count as f64) as u64
+    }
+
+    /**
+     * 根据直方图计算延迟的百分位，单位为 ms
+     */
+    fn percentile(&self, p: f64) -> u64 {
+        if self.latency_count == 0 {
+            return 0;
+        }
+        let rank = ((p / 100.0) * self.latency_count as f64).ceil().max(1.0) as u64;
+        let mut count = 0;
+        for (ms, n) in &self.latency_buckets {
+            count += n;
+            if count >= rank {
+                return *ms;
+            }
+        }
+        MAX_LATENCY_MS
+    }
+
+    fn max_latency(&self) -> u64 {
+        self.latency_buckets
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /**
+     * 汇总表格，每个元素为一行
+     */
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![
+            "================ 扫描统计 ================".to_owned(),
+            format!(
+                "请求数: {}, 耗时: {:.1}s, 速率: {:.1} req/s",
+                self.requests,
+                self.elapsed(),
+                self.requests_per_second()
+            ),
+            format!(
+                "延迟: avg {}ms, {}, max {}ms",
+                self.average_latency(),
+                PERCENTILES
+                    .iter()
+                    .map(|p| format!("p{} {}ms", p, self.percentile(*p)))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                self.max_latency()
+            ),
+        ];
+
+        if self.duplicates > 0 {
+            lines.push(format!("跳过的重复任务: {}", self.duplicates));
+        }
+        if !self.by_status.is_empty() {
+            lines.push("状态码:".to_owned());
+            for (code, count) in &self.by_status {
+                lines.push(format!("  {:<20} {:>10}", code, count));
+            }
+        }
+        if !self.by_suffix.is_empty() {
+            lines.push("后缀:".to_owned());
+            for (suffix, count) in &self.by_suffix {
+                let suffix = if suffix.is_empty() { "(none)" } else { suffix };
+                lines.push(format!("  {:<20} {:>10}", suffix, count));
+            }
+        }
+        if !self.by_error.is_empty() {
+            lines.push("错误:".to_owned());
+            for (kind, count) in &self.by_error {
+                lines.push(format!("  {:<20} {:>10}", kind.to_string(), count));
+            }
+        }
+        lines
+    }
+
+    /**
+     * 结构化的统计信息
+     */
+    pub fn to_json(&self) -> Value {
+        let percentiles = PERCENTILES
+            .iter()
+            .map(|p| (format!("p{}", p), json!(self.percentile(*p))))
+            .collect::<serde_json::Map<String, Value>>();
+
+        json!({
+            "requests": self.requests,
+            "elapsed_seconds": self.elapsed(),
+            "requests_per_second": self.requests_per_second(),
+            "duplicates": self.duplicates,
+            "latency_ms": {
+                "avg": self.average_latency(),
+                "max": self.max_latency(),
+                "percentiles": percentiles,
+            },
+            "status_codes": self
+                .by_status
+                .iter()
+                .map(|(k, v)| (k.to_string(), json!(v)))
+                .collect::<serde_json::Map<String, Value>>(),
+            "suffixes": self
+                .by_suffix
+                .iter()
+                .map(|(k, v)| (k.clone(), json!(v)))
+                .collect::<serde_json::Map<String, Value>>(),
+            "errors": self
+                .by_error
+                .iter()
+                .map(|(k, v)| (k.to_string(), json!(v)))
+                .collect::<serde_json::Map<String, Value>>(),
+        })
+    }
+}
@@ -0,0 +1,68 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use std::net::SocketAddr;
+use tokio::net::lookup_host;
+
+/**
+ * vhost 模式下使用的 DNS 解析，所有主机名都解析到 target 的地址
+ * URL 中的候选主机名作为 Host 头和 TLS SNI 发送，实际连接的目标保持不变
+ */
+#[derive(Debug, Clone)]
+pub struct FixedResolver {
+    addrs: Vec<SocketAddr>,
+}
+
+impl FixedResolver {
+    pub fn new(addrs: Vec<SocketAddr>) -> Self {
+        Self { addrs }
+    }
+}
+
+impl Resolve for FixedResolver {
+    fn resolve(&self, _: Name) -> Resolving {
+        // 端口由连接时的 URL 决定
+        let addrs = self.addrs.clone();
+        Box::pin(async move { Ok(Box::new(addrs.into_iter()) as Addrs) })
+    }
+}
+
+/**
+ * 解析 target 的地址，扫描期间所有候选主机名都连接到这些地址
+ */
+pub async fn resolve_target(target: &str) -> Result<Vec<SocketAddr>, &'static str> {
+    let url = Url::parse(target).map_err(|_| "target有误!")?;
+    let host = url.host_str().ok_or("target有误!")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    // IPv6 地址需要去掉方括号
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs = lookup_host((host, port))
+        .await
+        .map_err(|_| "无法解析 target 的地址！")?
+        .collect::<Vec<SocketAddr>>();
+    if addrs.is_empty() {
+        return Err("无法解析 target 的地址！");
+    }
+    Ok(addrs)
+}
+
+/**
+ * 把 target 中的主机名替换为候选的主机名，端口、路径和参数保持不变
+ */
+pub fn write_url(target: &str, host: &str, buffer: &mut String) {
+    let url = match Url::parse(target) {
+        Ok(v) => v,
+        Err(_) => {
+            buffer.push_str(target);
+            return;
+        }
+    };
+    buffer.push_str(url.scheme());
+    buffer.push_str("://");
+    buffer.push_str(host);
+    if let Some(port) = url.port() {
+        buffer.push(':');
+        buffer.push_str(&port.to_string());
+    }
+    buffer.push_str(&url[url::Position::BeforePath..]);
+}
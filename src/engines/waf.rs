@@ -0,0 +1,284 @@
+use crate::context::EnumResult;
+use reqwest::header::HeaderMap;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+// 滑动窗口大小，最近 WINDOW_SIZE 个响应中可疑响应过多时认为被拦截
+const WINDOW_SIZE: usize = 50;
+// 窗口中可疑响应的比例超过该值，并且扫描开始时的比例低于 REFERENCE_RATIO 时触发
+const BLOCK_RATIO: f64 = 0.8;
+const REFERENCE_RATIO: f64 = 0.3;
+// 用于计算扫描开始时响应分布的样本数量
+const REFERENCE_SIZE: u64 = 100;
+// 窗口中命中 WAF 特征的响应超过该数量时直接触发
+const SIGNATURE_THRESHOLD: usize = 10;
+
+// 常见 WAF 拦截页面的特征，(名称, 特征)，比较时页面内容已转为小写
+const BODY_SIGNATURES: [(&str, &str); 14] = [
+    ("Cloudflare", "attention required! | cloudflare"),
+    ("Cloudflare", "cf-chl-bypass"),
+    ("Akamai", "reference&#32;&#35;"),
+    ("AWS WAF", "request blocked.</h1>"),
+    ("ModSecurity", "mod_security"),
+    ("Imperva", "incapsula incident id"),
+    ("Sucuri", "sucuri website firewall"),
+    (
+        "F5 BIG-IP",
+        "the requested url was rejected. please consult with your administrator.",
+    ),
+    ("阿里云盾", "errors.aliyun.com"),
+    ("安全狗", "safedog"),
+    ("长亭雷池", "safeline"),
+    ("腾讯云 WAF", "waf.tencent-cloud.com"),
+    ("360 网站卫士", "wangzhan.360.cn"),
+    ("Captcha", "g-recaptcha"),
+];
+
+// 响应头特征，(名称, 响应头, 值中包含的内容)
+const HEADER_SIGNATURES: [(&str, &str, &str); 4] = [
+    ("Cloudflare", "cf-mitigated", ""),
+    ("Sucuri", "x-sucuri-block", ""),
+    ("阿里云盾", "server", "aliyun-waf"),
+    ("安全狗", "x-powered-by", "waf"),
+];
+
+/**
+ * 命中 WAF 后的处理策略
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WafPolicy {
+    // 只输出警告
+    Ignore,
+    // 暂停一段时间后继续
+    #[default]
+    Pause,
+    // 更换代理和 UA 后继续
+    Rotate,
+    // 终止扫描
+    Abort,
+}
+
+impl WafPolicy {
+    pub fn parse(raw: &str) -> Result<Self, &'static str> {
+        match raw.to_lowercase().as_str() {
+            "ignore" => Ok(WafPolicy::Ignore),
+            "pause" => Ok(WafPolicy::Pause),
+            "rotate" => Ok(WafPolicy::Rotate),
+            "abort" => Ok(WafPolicy::Abort),
+            _ => Err("waf-policy 只能为 ignore、pause、rotate 或 abort！"),
+        }
+    }
+}
+
+/**
+ * 根据响应识别 WAF 拦截页面，返回 WAF 的名称
+ */
+pub fn detect_block(code: u16, headers: &HeaderMap, body: Option<&str>) -> Option<&'static str> {
+    for (name, header, pattern) in HEADER_SIGNATURES {
+        let matched = headers
+            .get_all(header)
+            .iter()
+            .filter_map(|it| it.to_str().ok())
+            .any(|it| it.to_lowercase().contains(pattern));
+        if matched {
+            return Some(name);
+        }
+    }
+
+    // 正常页面中也可能出现这些关键字，只检查错误状态码的页面
+    if code < 400 {
+        return None;
+    }
+    let body = body?.to_lowercase();
+    BODY_SIGNATURES
+        .iter()
+        .find(|(_, pattern)| body.contains(pattern))
+        .map(|(name, _)| *name)
+}
+
+/**
+ * 根据名称查找 WAF 特征，用于还原从远程 worker 收到的检测结果
+ */
+pub fn find_signature(name: &str) -> Option<&'static str> {
+    HEADER_SIGNATURES
+        .iter()
+        .map(|(it, _, _)| *it)
+        .chain(BODY_SIGNATURES.iter().map(|(it, _)| *it))
+        .find(|it| *it == name)
+}
+
+/**
+ * 窗口中的一个响应，可疑响应的结果暂存在窗口中，确认不需要重新入队后再交给 saver
+ */
+#[derive(Debug)]
+struct Entry {
+    task: String,
+    suspicious: bool,
+    signature: bool,
+    result: Option<Arc<EnumResult>>,
+}
+
+/**
+ * 记录一个响应后的处理结果
+ */
+#[derive(Debug, Default)]
+pub struct Observation {
+    // 可以交给 saver 的结果
+    pub released: Vec<Arc<EnumResult>>,
+    // 触发拦截时窗口中可疑并且还没有重新入队过的任务及其结果
+    pub blocked: Option<Vec<(String, Arc<EnumResult>)>>,
+}
+
+/**
+ * 统计最近响应的分布，识别 WAF 拦截以及响应分布突变
+ */
+#[derive(Debug, Default)]
+pub struct WafMonitor {
+    window: VecDeque<Entry>,
+    reference_total: u64,
+    reference_suspicious: u64,
+    // 已经重新入队过的任务，每个任务最多重新入队一次
+    requeued: HashSet<String>,
+}
+
+impl WafMonitor {
+    /**
+     * 记录一个响应，可疑响应的结果暂存在窗口中，移出窗口后才交给 saver
+     * 触发拦截时清空窗口，返回其中可疑的任务，由调用方决定重新入队还是保留结果
+     */
+    pub fn observe(
+        &mut self,
+        task: &str,
+        code: u16,
+        signature: bool,
+        result: Arc<EnumResult>,
+    ) -> Observation {
+        let suspicious = signature || matches!(code, 403 | 429 | 503);
+        let mut observation = Observation::default();
+
+        if self.reference_total < REFERENCE_SIZE {
+            self.reference_total += 1;
+            if suspicious {
+                self.reference_suspicious += 1;
+            }
+        }
+
+        let result = if suspicious {
+            Some(result)
+        } else {
+            observation.released.push(result);
+            None
+        };
+        self.window.push_back(Entry {
+            task: task.to_owned(),
+            suspicious,
+            signature,
+            result,
+        });
+        if self.window.len() > WINDOW_SIZE {
+            observation
+                .released
+                .extend(self.window.pop_front().and_then(|it| it.result));
+        }
+        if self.window.len() < WINDOW_SIZE {
+            return observation;
+        }
+
+        let suspicious_count = self.window.iter().filter(|it| it.suspicious).count();
+        let signature_count = self.window.iter().filter(|it| it.signature).count();
+        let reference_ratio = self.reference_suspicious as f64 / self.reference_total as f64;
+        let shifted = suspicious_count as f64 / WINDOW_SIZE as f64 >= BLOCK_RATIO
+            && reference_ratio < REFERENCE_RATIO;
+
+        if shifted || signature_count >= SIGNATURE_THRESHOLD {
+            let mut blocked = vec![];
+            for entry in self.window.drain(..) {
+                match entry.result {
+                    Some(result) if !self.requeued.contains(&entry.task) => {
+                        blocked.push((entry.task, result))
+                    }
+                    Some(result) => observation.released.push(result),
+                    None => {}
+                }
+            }
+            observation.blocked = Some(blocked);
+        }
+        observation
+    }
+
+    /**
+     * 记录任务已经重新入队，之前已经重新入队过时返回 false
+     */
+    pub fn mark_requeued(&mut self, task: &str) -> bool {
+        self.requeued.insert(task.to_owned())
+    }
+
+    /**
+     * 暂停期间仍在进行中的请求返回了可疑响应时，丢弃最近一个响应暂存的结果，返回需要重新入队的任务
+     */
+    pub fn requeue_last(&mut self) -> Option<String> {
+        let entry = self.window.back_mut()?;
+        if entry.result.is_none() || !self.requeued.insert(entry.task.clone()) {
+            return None;
+        }
+        entry.result = None;
+        Some(entry.task.clone())
+    }
+
+    /**
+     * 扫描结束时取出窗口中暂存的全部结果
+     */
+    pub fn release_all(&mut self) -> Vec<Arc<EnumResult>> {
+        self.window.drain(..).filter_map(|it| it.result).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(monitor: &mut WafMonitor, task: &str, code: u16) -> Observation {
+        let result = EnumResult {
+            status_code: code,
+            url: task.to_owned(),
+            ..Default::default()
+        };
+        monitor.observe(task, code, false, Arc::new(result))
+    }
+
+    #[test]
+    fn requeue_blocked_tasks_once() {
+        let mut monitor = WafMonitor::default();
+        for i in 0..REFERENCE_SIZE {
+            let observation = observe(&mut monitor, &format!("ok{}", i), 404);
+            assert_eq!(observation.released.len(), 1);
+            assert!(observation.blocked.is_none());
+        }
+
+        // 可疑响应的结果暂存在窗口中，触发后全部返回
+        let mut blocked = None;
+        for i in 0..WINDOW_SIZE {
+            let observation = observe(&mut monitor, &format!("b{}", i), 403);
+            if observation.blocked.is_some() {
+                blocked = observation.blocked;
+                break;
+            }
+        }
+        let blocked = blocked.expect("distribution shift should trigger");
+        assert!(blocked.len() >= (WINDOW_SIZE as f64 * BLOCK_RATIO) as usize);
+        for (task, _) in &blocked {
+            assert!(monitor.mark_requeued(task));
+        }
+
+        // 重新入队过的任务再次被拦截时保留结果，不再重新入队
+        let (task, _) = &blocked[0];
+        let observation = observe(&mut monitor, task, 403);
+        assert!(observation.released.is_empty());
+        assert_eq!(monitor.requeue_last(), None);
+        assert_eq!(monitor.release_all().len(), 1);
+
+        observe(&mut monitor, "late", 403);
+        assert_eq!(monitor.requeue_last().as_deref(), Some("late"));
+        assert!(monitor.release_all().is_empty());
+    }
+}
@@ -1,10 +1,13 @@
 use std::process::exit;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use log::error;
-use tokio::sync::Mutex;
 
-use crate::context::{EnumResult, WorkerStatus};
+use crate::args_parser::{AppArgs, Command};
+use crate::context::{AppContext, EnumProgressBar, EnumResult, Progress};
+use crate::engines::distributed::Role;
+use crate::engines::queue::TaskQueue;
 
 mod args_parser;
 mod context;
@@ -15,7 +18,11 @@ mod utils;
 async fn main() {
     utils::init_logger();
     let args = match args_parser::parse().await {
-        Ok(v) => Arc::new(v),
+        Ok(Command::Scan(v)) => Arc::<AppArgs>::from(v),
+        Ok(Command::Merge(v)) => {
+            engines::merge(&v).await;
+            return;
+        }
         Err(e) => {
             error!("{}", e);
             exit(-1);
@@ -23,57 +30,139 @@ async fn main() {
     };
 
     // 初始化 app context
-    let app_context = Arc::new(Mutex::new(context::AppContext::new()));
+    let app_context = Arc::new(context::AppContext::new());
 
-    // 任务通道
-    let (task_tx, task_rx) = async_channel::bounded::<String>(1024);
-    let (saver_tx, saver_rx) = async_channel::bounded::<Arc<EnumResult>>(1024);
+    // 协调者只生成任务和保存结果，不发送请求
+    if let Role::Coordinator(listen) = &args.role {
+        coordinate(listen.to_owned(), args, app_context).await;
+        return;
+    }
 
-    // 启动不同的协程
-    // task builder
-    {
-        app_context.lock().await.builder_status = WorkerStatus::Running;
+    // 预检目标是否支持 HEAD 方法
+    if engines::preflight(&args).await {
+        app_context.head_fallback.store(true, Ordering::Relaxed);
     }
-    let task_builder_handler = tokio::spawn(engines::builder(
-        task_tx.clone(),
-        Arc::clone(&args),
-        Arc::clone(&app_context),
-    ));
 
+    // 构建所有 worker 共用的 HTTP Client
+    *app_context.clients.write().unwrap() = engines::build_http_clients(&args, 0);
+
+    // 任务队列和结果通道，远程 worker 需要把处理完成的任务确认给协调者
+    let progress = Arc::new(Progress::default());
+    let (task_queue, acks) = match args.role {
+        Role::Worker(_) => {
+            let (task_queue, acks) = TaskQueue::with_acks(1024, Arc::clone(&progress));
+            (Arc::new(task_queue), Some(acks))
+        }
+        _ => (Arc::new(TaskQueue::new(1024, Arc::clone(&progress))), None),
+    };
+    let (saver_tx, saver_rx) = async_channel::bounded::<Arc<EnumResult>>(1024);
+
+    // 启动不同的协程
     // worker
     let mut worker_handlers = vec![];
     for idx in 0..args.task_count {
-        {
-            app_context
-                .lock()
-                .await
-                .worker_status
-                .push(WorkerStatus::Running);
-        }
         let _handler = tokio::spawn(engines::worker(
             idx,
             Arc::clone(&args),
-            task_rx.clone(),
+            Arc::clone(&task_queue),
             saver_tx.clone(),
             Arc::clone(&app_context),
+            Arc::clone(&progress),
         ));
         worker_handlers.push(_handler);
     }
+    // 结果通道的发送端只由 worker 持有，worker 全部退出后 saver 随之退出
+    drop(saver_tx);
 
-    // saver
-    {
-        app_context.lock().await.saver_status = WorkerStatus::Running;
+    let pb = EnumProgressBar::new();
+    let mut handlers = vec![];
+    if let (Role::Worker(coordinator), Some(acks)) = (&args.role, acks) {
+        // 远程 worker 从协调者获取任务，结果发送回协调者
+        handlers.push(tokio::spawn(engines::distributed::remote(
+            coordinator.to_owned(),
+            Arc::clone(&args),
+            Arc::clone(&app_context),
+            Arc::clone(&task_queue),
+            acks,
+            saver_rx,
+        )));
+    } else {
+        // task builder 和 saver
+        handlers.push(tokio::spawn(engines::builder(
+            Arc::clone(&task_queue),
+            Arc::clone(&args),
+            Arc::clone(&app_context),
+        )));
+        handlers.push(tokio::spawn(engines::saver(
+            Arc::clone(&app_context),
+            Arc::clone(&args),
+            saver_rx,
+            pb.clone(),
+            Arc::clone(&progress),
+        )));
     }
-    let saver_handler = tokio::spawn(engines::saver(
-        Arc::clone(&app_context),
+    let reporter_handler = tokio::spawn(engines::reporter(
+        pb,
+        Arc::clone(&progress),
+        Arc::clone(&task_queue),
+    ));
+
+    // 目标存活监控
+    let canary_handler = tokio::spawn(engines::canary(
         Arc::clone(&args),
-        saver_rx.clone(),
+        Arc::clone(&app_context),
+        Arc::clone(&task_queue),
     ));
 
     // 等待结束
-    let _ = task_builder_handler.await;
     for h in worker_handlers {
         let _ = h.await;
     }
+    let _ = canary_handler.await;
+    let _ = reporter_handler.await;
+    for h in handlers {
+        let _ = h.await;
+    }
+}
+
+/**
+ * 协调者模式，任务由 builder 生成后分发给远程 worker，远程 worker 返回的结果交给 saver
+ */
+async fn coordinate(listen: String, args: Arc<AppArgs>, app_context: Arc<AppContext>) {
+    let progress = Arc::new(Progress::default());
+    let task_queue = Arc::new(TaskQueue::new(1024, Arc::clone(&progress)));
+    let (saver_tx, saver_rx) = async_channel::bounded::<Arc<EnumResult>>(1024);
+
+    let task_builder_handler = tokio::spawn(engines::builder(
+        Arc::clone(&task_queue),
+        Arc::clone(&args),
+        Arc::clone(&app_context),
+    ));
+    let coordinator_handler = tokio::spawn(engines::distributed::coordinator(
+        listen,
+        Arc::clone(&args),
+        Arc::clone(&app_context),
+        Arc::clone(&task_queue),
+        saver_tx,
+        Arc::clone(&progress),
+    ));
+
+    let pb = EnumProgressBar::new();
+    let saver_handler = tokio::spawn(engines::saver(
+        Arc::clone(&app_context),
+        Arc::clone(&args),
+        saver_rx,
+        pb.clone(),
+        Arc::clone(&progress),
+    ));
+    let reporter_handler = tokio::spawn(engines::reporter(
+        pb,
+        Arc::clone(&progress),
+        Arc::clone(&task_queue),
+    ));
+
+    let _ = task_builder_handler.await;
+    let _ = coordinator_handler.await;
+    let _ = reporter_handler.await;
     let _ = saver_handler.await;
 }